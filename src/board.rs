@@ -1,384 +1,3052 @@
-use crate::board::Direction::{DECREASING, INCREASING, STABLE};
-use std::collections::HashMap;
+use rand::seq::SliceRandom;
+use std::fmt;
+use std::ops::Deref;
 
-pub const WIDTH: u8 = 7;
-pub const HEIGHT: u8 = 6;
+pub const DEFAULT_WIDTH: u8 = 7;
+pub const DEFAULT_HEIGHT: u8 = 6;
+pub const DEFAULT_CONNECT: u8 = 4;
+pub const MIN_CONNECT: u8 = 3;
+pub const MAX_CONNECT: u8 = 6;
+pub const MIN_PLAYERS: u8 = 2;
+pub const MAX_PLAYERS: u8 = 4;
+// Score Four: a 4x4 grid of pegs, each 4 tokens high
+pub const SCORE_FOUR_SIZE: u8 = 4;
 
-#[derive(Clone, PartialEq)]
+// Bitboards are stored in a u128, and serialization packs each cell as a digit in a u128, in
+// base players + 1, plus one for boards with blockers.
+const MAX_BITS: u16 = 128;
+
+// Columns are written as a single base 36 digit in the move notation, starting from 1
+const MAX_COLUMNS: u8 = 35;
+
+// Number of line directions on a three-dimensional board, flat boards only have the first 4
+const MAX_DIRECTIONS: usize = 13;
+
+// A blocker in the text formats, and its piece number for the zobrist keys, after the players
+const BLOCKER_CHAR: char = 'X';
+const BLOCKER_PIECE: usize = MAX_PLAYERS as usize;
+
+// The fields that end a position written by to_fen on a board that wraps around and on one with
+// room for blockers
+const WRAP_FIELD: &str = "wrap";
+const BLOCKERS_FIELD: &str = "blockers";
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Config {
+    pub width: u8,
+    pub height: u8,
+    // Number of rows of columns from front to back. Boards deeper than 1 are three-dimensional,
+    // with lines going in any direction across the rows.
+    pub depth: u8,
+    // Number of tokens in a row needed to win
+    pub connect: u8,
+    // Whether the left and right edges are joined, so that lines can go around the board
+    pub wrap: bool,
+    // Number of players taking turns, each with their own color
+    pub players: u8,
+    // Whether cells can hold neutral blockers, which belong to nobody and stop every line
+    pub blockers: bool,
+}
+
+impl Config {
+    pub fn new(width: u8, height: u8, connect: u8) -> Result<Config, &'static str> {
+        if width == 0 || height == 0 {
+            return Result::Err("the board must have at least one row and one column");
+        }
+        if width as u16 * (height as u16 + 1) > MAX_BITS || width > MAX_COLUMNS {
+            return Result::Err("the board is too large");
+        }
+        if !(MIN_CONNECT..=MAX_CONNECT).contains(&connect) {
+            return Result::Err("the winning line must be between 3 and 6 tokens long");
+        }
+        let config = Config {
+            width,
+            height,
+            depth: 1,
+            connect,
+            wrap: false,
+            players: MIN_PLAYERS,
+            blockers: false,
+        };
+        Result::Ok(config)
+    }
+
+    // The same board shared by more players
+    pub fn with_players(self, players: u8) -> Result<Config, &'static str> {
+        if !(MIN_PLAYERS..=MAX_PLAYERS).contains(&players) {
+            return Result::Err("there must be between 2 and 4 players");
+        }
+        Result::Ok(Config { players, ..self })
+    }
+
+    // The same board with room for blockers
+    pub fn with_blockers(self, blockers: bool) -> Result<Config, &'static str> {
+        Result::Ok(Config { blockers, ..self })
+    }
+
+    // Number of values a cell can hold: empty, a token of any player or a blocker
+    fn cell_states(&self) -> u128 {
+        self.players as u128 + 1 + self.blockers as u128
+    }
+
+    // Number of positions to_number can write, one for each value of every cell. None if there
+    // are too many for a u128, when every player and blocker adds a value to each cell of a
+    // large board.
+    fn number_range(&self) -> Option<u128> {
+        let cells = self.columns() as u32 * self.height as u32;
+        self.cell_states().checked_pow(cells)
+    }
+
+    // The same board rolled into a cylinder. A line cannot be longer than the board is wide,
+    // or it would go around and count some tokens twice.
+    pub fn with_wrap(self, wrap: bool) -> Result<Config, &'static str> {
+        if wrap && self.connect > self.width {
+            return Result::Err("the winning line must not be longer than the board is wide");
+        }
+        if wrap && self.depth > 1 {
+            return Result::Err("only flat boards can wrap around");
+        }
+        Result::Ok(Config { wrap, ..self })
+    }
+
+    // The same board with the given number of rows of columns, one behind the other
+    pub fn with_depth(self, depth: u8) -> Result<Config, &'static str> {
+        if depth == 0 {
+            return Result::Err("the board must have at least one row of columns");
+        }
+        if depth > 1 && self.wrap {
+            return Result::Err("only flat boards can wrap around");
+        }
+        let config = Config { depth, ..self };
+        if config.total_bits() > MAX_BITS as u32 || config.columns() > MAX_COLUMNS {
+            return Result::Err("the board is too large");
+        }
+        Result::Ok(config)
+    }
+
+    // Score Four, the three-dimensional game on a 4x4 grid of pegs
+    pub fn score_four() -> Config {
+        Config {
+            width: SCORE_FOUR_SIZE,
+            height: SCORE_FOUR_SIZE,
+            depth: SCORE_FOUR_SIZE,
+            ..Config::default()
+        }
+    }
+
+    // Number of columns tokens can be dropped in. They are numbered from 1, left to right and
+    // then row after row from the front.
+    pub fn columns(&self) -> u8 {
+        self.width * self.depth
+    }
+
+    // Number of the column at the given place, counting both from 1
+    pub fn column_at(&self, x: u8, z: u8) -> u8 {
+        (z - 1) * self.width + x
+    }
+
+    // Place of the column from the left and from the front, counting both from 1
+    pub fn column_place(&self, column: u8) -> (u8, u8) {
+        ((column - 1) % self.width + 1, (column - 1) / self.width + 1)
+    }
+
+    // Every column takes height + 1 bits: the extra bit on top is always empty and acts as a
+    // separator, so that shifting a line past the top of a column never lands on the next one.
+    fn column_bits(&self) -> u8 {
+        self.height + 1
+    }
+
+    // Bits taken by a row of columns. On a three-dimensional board every row ends with an
+    // extra column that is always empty, for the same reason as the bit on top of the columns.
+    fn row_bits(&self) -> u32 {
+        let columns = if self.depth > 1 {
+            self.width + 1
+        } else {
+            self.width
+        };
+        columns as u32 * self.column_bits() as u32
+    }
+
+    fn total_bits(&self) -> u32 {
+        self.row_bits() * self.depth as u32
+    }
+
+    // Index of the bottom cell of the column in the bitboard
+    fn column_start(&self, column: u8) -> u32 {
+        let (x, z) = self.column_place(column);
+        (z - 1) as u32 * self.row_bits() + (x - 1) as u32 * self.column_bits() as u32
+    }
+
+    // Shift amounts for the line directions: vertical, horizontal and the two diagonals on a
+    // flat board. Three-dimensional boards add the nine directions going to the next row, 13 in
+    // all.
+    fn directions(&self) -> Directions {
+        let column_bits = self.column_bits() as u32;
+        let mut directions = Directions {
+            shifts: [0; MAX_DIRECTIONS],
+            len: 4,
+        };
+        directions.shifts[..4].copy_from_slice(&[1, column_bits, column_bits + 1, column_bits - 1]);
+        if self.depth > 1 {
+            for dx in -1..=1 {
+                for dy in -1..=1 {
+                    let shift = self.row_bits() as i32 + dx * column_bits as i32 + dy;
+                    directions.shifts[directions.len] = shift as u32;
+                    directions.len += 1;
+                }
+            }
+        }
+        directions
+    }
+}
+
+// The shift amounts of the line directions of a board, kept in an array rather than a Vec since
+// they are looked up at every move
+struct Directions {
+    shifts: [u32; MAX_DIRECTIONS],
+    len: usize,
+}
+
+impl Deref for Directions {
+    type Target = [u32];
+
+    fn deref(&self) -> &[u32] {
+        &self.shifts[..self.len]
+    }
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            width: DEFAULT_WIDTH,
+            height: DEFAULT_HEIGHT,
+            depth: 1,
+            connect: DEFAULT_CONNECT,
+            wrap: false,
+            players: MIN_PLAYERS,
+            blockers: false,
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
 pub enum Token {
     YELLOW,
     RED,
+    GREEN,
+    BLUE,
 }
 
-#[derive(Clone)]
-enum Direction {
-    INCREASING,
-    DECREASING,
-    STABLE,
+// Every token in turn order, the first player being yellow
+pub const TOKENS: [Token; MAX_PLAYERS as usize] =
+    [Token::YELLOW, Token::RED, Token::GREEN, Token::BLUE];
+
+impl Token {
+    // The player after this one, when the given number of players take turns
+    pub fn next(&self, players: u8) -> Token {
+        TOKENS[(self.index() + 1) % players as usize].clone()
+    }
+
+    #[allow(dead_code)]
+    pub fn previous(&self, players: u8) -> Token {
+        TOKENS[(self.index() + players as usize - 1) % players as usize].clone()
+    }
+
+    pub fn index(&self) -> usize {
+        match self {
+            Token::YELLOW => 0,
+            Token::RED => 1,
+            Token::GREEN => 2,
+            Token::BLUE => 3,
+        }
+    }
 }
 
-struct Direction2D {
-    pub x: Direction,
-    pub y: Direction,
+// A move on the board: dropping a token on top of a column, or popping the token at the bottom
+// of a column out, which makes everything above it fall down by one cell. The other moves play
+// the special discs of Power Up:
+//  - ANVIL: knocks every token of the column off the board and lands at the bottom
+//  - WALL: a disc that belongs to nobody, like a blocker
+//  - BOMB: pops the token of an opponent out of the bottom of the column, and is gone with it
+//  - EXTRA: a token of the player, who then moves again
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum Action {
+    DROP(u8),
+    POP(u8),
+    ANVIL(u8),
+    WALL(u8),
+    BOMB(u8),
+    EXTRA(u8),
 }
 
-impl Direction2D {
-    fn left() -> Direction2D {
-        Direction2D {
-            x: DECREASING,
-            y: STABLE,
+impl Action {
+    pub fn column(&self) -> u8 {
+        match self {
+            Action::DROP(column)
+            | Action::POP(column)
+            | Action::ANVIL(column)
+            | Action::WALL(column)
+            | Action::BOMB(column)
+            | Action::EXTRA(column) => *column,
         }
     }
-    fn right() -> Direction2D {
-        Direction2D {
-            x: INCREASING,
-            y: STABLE,
+
+    // The same kind of move in another column
+    pub fn in_column(&self, column: u8) -> Action {
+        match self {
+            Action::DROP(_) => Action::DROP(column),
+            Action::POP(_) => Action::POP(column),
+            Action::ANVIL(_) => Action::ANVIL(column),
+            Action::WALL(_) => Action::WALL(column),
+            Action::BOMB(_) => Action::BOMB(column),
+            Action::EXTRA(_) => Action::EXTRA(column),
         }
     }
-    fn up() -> Direction2D {
-        Direction2D {
-            x: STABLE,
-            y: INCREASING,
+
+    // Character written before the column in the moves notation, drops having none
+    pub fn prefix(&self) -> Option<char> {
+        match self {
+            Action::DROP(_) => None,
+            Action::POP(_) => Some('-'),
+            Action::ANVIL(_) => Some('!'),
+            Action::WALL(_) => Some('#'),
+            Action::BOMB(_) => Some('*'),
+            Action::EXTRA(_) => Some('+'),
         }
     }
-    fn down() -> Direction2D {
-        Direction2D {
-            x: STABLE,
-            y: DECREASING,
-        }
+
+    // The move written with the given prefix, if it is one
+    pub fn from_prefix(prefix: char, column: u8) -> Option<Action> {
+        [
+            Action::POP(column),
+            Action::ANVIL(column),
+            Action::WALL(column),
+            Action::BOMB(column),
+            Action::EXTRA(column),
+        ]
+        .iter()
+        .find(|action| action.prefix() == Some(prefix))
+        .cloned()
     }
-    fn upleft() -> Direction2D {
-        Direction2D {
-            x: DECREASING,
-            y: INCREASING,
+
+    // Reads a sequence of moves in the notation of Board::to_moves. The columns are only checked
+    // against the board when the moves are played.
+    pub fn parse_moves(value: &str) -> Result<Vec<Action>, PositionError> {
+        let mut actions = Vec::new();
+        let mut chars = value.chars();
+        while let Some(c) = chars.next() {
+            let action = match Action::from_prefix(c, 0) {
+                Some(action) => match chars.next() {
+                    Some(c) => action.in_column(Action::parse_column(c)?),
+                    None => return Result::Err(PositionError::InvalidFormat("missing column")),
+                },
+                None => Action::DROP(Action::parse_column(c)?),
+            };
+            actions.push(action);
         }
+        Result::Ok(actions)
     }
-    fn upright() -> Direction2D {
-        Direction2D {
-            x: INCREASING,
-            y: INCREASING,
+
+    fn parse_column(c: char) -> Result<u8, PositionError> {
+        match c.to_digit(36) {
+            Some(column) => Result::Ok(column as u8),
+            None => Result::Err(PositionError::InvalidCharacter(c)),
         }
     }
-    fn downleft() -> Direction2D {
-        Direction2D {
-            x: DECREASING,
-            y: DECREASING,
-        }
+
+    // Whether both moves are of the same kind, whatever their columns
+    pub fn same_kind(&self, other: &Action) -> bool {
+        self.in_column(0) == other.in_column(0)
     }
-    fn downright() -> Direction2D {
-        Direction2D {
-            x: INCREASING,
-            y: INCREASING,
+}
+
+// Whether a cell is on an odd or even row, counting from 1 at the bottom. With yellow moving
+// first, odd threats are usually good for yellow and even threats for red.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Parity {
+    ODD,
+    EVEN,
+}
+
+// An empty cell that would complete a line for a player once a token of theirs lands there
+#[derive(Clone, PartialEq, Debug)]
+pub struct Threat {
+    pub column: u8,
+    pub row: u8,
+    pub parity: Parity,
+}
+
+// A winning move: the token that won and the lines it completed, as (column, row) cells
+#[derive(Clone, PartialEq, Debug)]
+pub struct Win {
+    pub token: Token,
+    pub lines: Vec<Vec<(u8, u8)>>,
+}
+
+// Reasons for rejecting a position loaded with try_from_number or from_string
+#[derive(Clone, PartialEq, Debug)]
+pub enum PositionError {
+    // The number encodes more cells than the board has
+    Overflow,
+    // The board has too many cells, players and blockers for a position to fit in a number
+    TooManyCells,
+    WrongLength { expected: usize, found: usize },
+    InvalidCharacter(char),
+    // A token with an empty cell below it
+    FloatingToken { column: u8, row: u8 },
+    // The players take turns, so their token counts can differ by one at most
+    TokenCountImbalance { most: u32, fewest: u32 },
+    // More than one player has a line
+    BothPlayersWin,
+    InvalidFormat(&'static str),
+    InvalidSize(&'static str),
+    // The side to move already has more tokens than the other one
+    WrongSideToMove,
+    // A player has more tokens than one who moves before them
+    OutOfTurn,
+    MoveCountMismatch { expected: u32, found: u32 },
+    // A move of a sequence that cannot be played, numbered from 1
+    IllegalMove { index: usize, reason: &'static str },
+}
+
+impl fmt::Display for PositionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PositionError::Overflow => write!(f, "the position does not fit in the board"),
+            PositionError::TooManyCells => {
+                write!(f, "the board is too large to be written as a number")
+            }
+            PositionError::WrongLength { expected, found } => write!(
+                f,
+                "the position has {} cells instead of {}",
+                found, expected
+            ),
+            PositionError::InvalidCharacter(c) => {
+                write!(f, "invalid character '{}' in the position", c)
+            }
+            PositionError::FloatingToken { column, row } => write!(
+                f,
+                "the token at column {}, row {} has nothing below it",
+                column, row
+            ),
+            PositionError::TokenCountImbalance { most, fewest } => write!(
+                f,
+                "impossible token count: {} for a player and {} for another",
+                most, fewest
+            ),
+            PositionError::BothPlayersWin => write!(f, "more than one player has a winning line"),
+            PositionError::InvalidFormat(message) => write!(f, "invalid format: {}", message),
+            PositionError::InvalidSize(message) => write!(f, "invalid size: {}", message),
+            PositionError::OutOfTurn => {
+                write!(f, "a player has more tokens than one who moves before them")
+            }
+            PositionError::WrongSideToMove => {
+                write!(f, "the side to move does not match the token counts")
+            }
+            PositionError::MoveCountMismatch { expected, found } => write!(
+                f,
+                "the move count is {} but there are {} tokens",
+                found, expected
+            ),
+            PositionError::IllegalMove { index, reason } => {
+                write!(f, "move {} is illegal: {}", index, reason)
+            }
         }
     }
 }
 
-type Index = (u8, u8);
+// A move of the history: the player who made it, the tokens it knocked off the board, from the
+// bottom up, so that it can be taken back, and the zobrist hash of the position it was made in
+#[derive(Clone)]
+struct PlayedMove {
+    action: Action,
+    token: Token,
+    removed: Vec<Token>,
+    hash: u64,
+}
 
+#[derive(Clone)]
 pub struct Board {
-    tokens: HashMap<Index, Token>,
+    config: Config,
+    // The cells taken by each player, indexed by Token::index
+    positions: [u128; MAX_PLAYERS as usize],
+    // The cells taken by blockers. They are set up before the game, apart from the walls played
+    // in Power Up.
+    blockers: u128,
+    // The blockers set up before the game, without the walls, which reset puts back
+    start_blockers: u128,
+    // Number of blockers dropped in random columns before the game, which reset drops again
+    random_blockers: u8,
+    // Zobrist hash of the tokens on the board, updated every time a token is added or removed
+    hash: u64,
+    // Moves played through play, add_token and pop_token, oldest first, and the moves taken
+    // back by undo_move
+    history: Vec<PlayedMove>,
+    undone: Vec<PlayedMove>,
 }
 
 impl Board {
+    #[allow(dead_code)]
     pub fn new() -> Board {
+        Board::with_config(Config::default())
+    }
+
+    pub fn with_config(config: Config) -> Board {
         Board {
-            tokens: HashMap::new(),
+            config,
+            positions: [0; MAX_PLAYERS as usize],
+            blockers: 0,
+            start_blockers: 0,
+            random_blockers: 0,
+            hash: 0,
+            history: Vec::new(),
+            undone: Vec::new(),
         }
     }
 
-    fn cell_to_char(&self, x: u8, y: u8) -> char {
-        match self.tokens.get(&(x, y)) {
-            Some(color) => match color {
-                Token::YELLOW => 'Y',
-                Token::RED => 'R',
-            },
-            None => '_',
+    pub fn config(&self) -> Config {
+        self.config
+    }
+
+    pub fn width(&self) -> u8 {
+        self.config.width
+    }
+
+    pub fn columns(&self) -> u8 {
+        self.config.columns()
+    }
+
+    pub fn height(&self) -> u8 {
+        self.config.height
+    }
+
+    pub fn players(&self) -> u8 {
+        self.config.players
+    }
+
+    // The player whose turn comes after the given one
+    pub fn next_player(&self, token: &Token) -> Token {
+        token.next(self.config.players)
+    }
+
+    fn occupied(&self) -> u128 {
+        self.positions
+            .iter()
+            .fold(self.blockers, |occupied, position| occupied | position)
+    }
+
+    // Cells are given as (column, row), the column being numbered as in Config::columns
+    fn cell_index(&self, x: u8, y: u8) -> u32 {
+        self.config.column_start(x) + (y - 1) as u32
+    }
+
+    fn index_to_cell(&self, index: u32) -> (u8, u8) {
+        let column_bits = self.config.column_bits() as u32;
+        let row_index = index % self.config.row_bits();
+        let x = (row_index / column_bits + 1) as u8;
+        let z = (index / self.config.row_bits() + 1) as u8;
+        (
+            self.config.column_at(x, z),
+            (row_index % column_bits + 1) as u8,
+        )
+    }
+
+    fn cell_bit(&self, x: u8, y: u8) -> u128 {
+        1 << self.cell_index(x, y)
+    }
+
+    // Number of tokens in a column, found from the highest occupied cell, blockers included.
+    // Columns outside the board have no tokens.
+    pub fn column_height(&self, column: u8) -> u8 {
+        if !(1..=self.config.columns()).contains(&column) {
+            return 0;
         }
+        let column_mask = (1u128 << self.config.height) - 1;
+        let shift = self.config.column_start(column);
+        let column_bits = (self.occupied() >> shift) & column_mask;
+        (128 - column_bits.leading_zeros()) as u8
     }
 
-    #[allow(dead_code)]
-    pub fn to_string(&self) -> String {
-        let mut value = String::new();
-        for x in 1..=WIDTH {
-            for y in 1..=HEIGHT {
-                value.push(self.cell_to_char(x, y));
-            }
+    fn cell_to_char(&self, x: u8, y: u8) -> char {
+        match self.get_color_at_cell(x, y) {
+            Some(color) => Board::token_to_char(color),
+            None if self.is_blocker(x, y) => BLOCKER_CHAR,
+            None => '_',
         }
-        value
     }
 
+    // Tokens are numbered from 1 in turn order, a blocker comes after the last player
+    #[allow(dead_code)]
     fn cell_to_number(&self, x: u8, y: u8) -> u128 {
-        match self.tokens.get(&(x, y)) {
-            Some(color) => match color {
-                Token::YELLOW => 1,
-                Token::RED => 2,
-            },
+        match self.get_color_at_cell(x, y) {
+            Some(color) => color.index() as u128 + 1,
+            None if self.is_blocker(x, y) => self.config.players as u128 + 1,
             None => 0,
         }
     }
 
-    pub fn to_number(&self) -> u128 {
+    #[allow(dead_code)]
+    pub fn to_number(&self) -> Result<u128, PositionError> {
+        if self.config.number_range().is_none() {
+            return Result::Err(PositionError::TooManyCells);
+        }
+        let columns = self.config.columns();
         let mut value = 0;
-        for x in 1..=WIDTH {
-            for y in 1..=HEIGHT {
-                value +=
-                    (3 as u128).pow(((y - 1) * WIDTH + (x - 1)) as u32) * self.cell_to_number(x, y);
+        for x in 1..=columns {
+            for y in 1..=self.config.height {
+                let exponent = (y - 1) as u32 * columns as u32 + (x - 1) as u32;
+                value += self.config.cell_states().pow(exponent) * self.cell_to_number(x, y);
             }
         }
-        value
+        Result::Ok(value)
     }
 
-    pub fn from_number(value: u128) -> Board {
-        let mut tokens = HashMap::new();
+    #[allow(dead_code)]
+    pub fn from_number(value: u128, config: Config) -> Board {
+        let mut board = Board::with_config(config);
         let mut current_value = value;
 
-        for y in 1..=HEIGHT {
-            for x in 1..=WIDTH {
-                let current_cell = current_value % 3;
-                match current_cell {
-                    1 => {
-                        tokens.insert((x, y), Token::YELLOW);
-                    }
-                    2 => {
-                        tokens.insert((x, y), Token::RED);
-                    }
-                    _ => (),
+        for y in 1..=config.height {
+            for x in 1..=config.columns() {
+                let current_cell = (current_value % config.cell_states()) as usize;
+                if current_cell > config.players as usize {
+                    board.place_blocker(x, y);
+                } else if current_cell > 0 {
+                    board.set_cell(x, y, Some(TOKENS[current_cell - 1].clone()));
                 }
-                current_value = current_value / 3;
+                current_value /= config.cell_states();
             }
         }
 
-        Board { tokens: tokens }
+        board
     }
 
-    pub fn add_token(&mut self, column: u8, color: &Token) -> Result<bool, &str> {
-        if column < 1 || column > WIDTH {
-            return Result::Err("out of bounds");
-        }
-
-        for h in 1..=HEIGHT {
-            if !self.tokens.contains_key(&(column, h)) {
-                self.tokens.insert((column, h), color.clone());
-                return Result::Ok(true);
+    // Returns the board reflected left to right, history included
+    pub fn mirror(&self) -> Board {
+        let config = self.config;
+        let mirror_column = |column: u8| {
+            let (x, z) = config.column_place(column);
+            config.column_at(config.width + 1 - x, z)
+        };
+        let column_mask = (1u128 << config.column_bits()) - 1;
+        let mirror_bits = |position: u128| {
+            let mut mirrored = 0;
+            for column in 1..=config.columns() {
+                let bits = (position >> config.column_start(column)) & column_mask;
+                mirrored |= bits << config.column_start(mirror_column(column));
             }
+            mirrored
+        };
+        let mirror_move = |played: &PlayedMove| PlayedMove {
+            action: played
+                .action
+                .in_column(mirror_column(played.action.column())),
+            ..played.clone()
+        };
+
+        let mut mirrored = Board {
+            config: self.config,
+            positions: [
+                mirror_bits(self.positions[0]),
+                mirror_bits(self.positions[1]),
+                mirror_bits(self.positions[2]),
+                mirror_bits(self.positions[3]),
+            ],
+            blockers: mirror_bits(self.blockers),
+            start_blockers: mirror_bits(self.start_blockers),
+            random_blockers: self.random_blockers,
+            hash: 0,
+            history: self.history.iter().map(mirror_move).collect(),
+            undone: self.undone.iter().map(mirror_move).collect(),
+        };
+        mirrored.hash = mirrored.compute_hash();
+        // The positions the moves were made in are reflected too
+        let mut earlier = mirrored.clone();
+        for played in mirrored.history.iter_mut().rev() {
+            earlier.undo_move();
+            played.hash = earlier.hash;
         }
+        mirrored
+    }
 
-        Result::Ok(false)
+    // A key shared by a position and its mirror image, the smallest of their to_number values
+    #[allow(dead_code)]
+    pub fn canonical_key(&self) -> Result<u128, PositionError> {
+        Result::Ok(self.to_number()?.min(self.mirror().to_number()?))
     }
 
-    pub fn get_color_at_cell(&self, x: u8, y: u8) -> Option<&Token> {
-        self.tokens.get(&(x, y))
+    // Like from_number, but rejects the positions that cannot be reached in a game
+    #[allow(dead_code)]
+    pub fn try_from_number(value: u128, config: Config) -> Result<Board, PositionError> {
+        match config.number_range() {
+            None => return Result::Err(PositionError::TooManyCells),
+            Some(range) if value >= range => return Result::Err(PositionError::Overflow),
+            Some(_) => (),
+        }
+        let board = Board::from_number(value, config);
+        board.validate()?;
+        Result::Ok(board)
     }
 
-    fn linear_step(coord: u8, dir: &Direction, bound: u8) -> Option<u8> {
-        match match dir {
-            STABLE => coord,
-            INCREASING => coord + 1,
-            DECREASING => coord - 1,
-        } {
-            x if x >= 1 && x <= bound => Some(x),
-            _ => None,
+    // Text formats. Both list the cells row by row starting from the bottom left, like to_number,
+    // with 'Y', 'R', 'G' and 'B' for the tokens, 'X' for a blocker and '_' for an empty cell.
+    //  - to_string / from_string: just the cells, all on one line.
+    //  - to_fen / from_fen: the rows from top to bottom separated by '/', followed by the side to
+    //    move, the number of moves played, the length of the winning line and the number of
+    //    players. An empty 7x6 board is "_______/_______/_______/_______/_______/_______ Y 0 4 2".
+    //    On a three-dimensional board each row lists the rows of columns from the front separated
+    //    by '|', e.g. "____|____|____|____" for a row of Score Four. A board that wraps around
+    //    ends with a "wrap" field, and one with room for blockers with a "blockers" field.
+    //  - to_moves / from_moves: the columns played, numbered from 1, yellow moving first. This is
+    //    the notation used by most solvers, e.g. "4453". Boards wider than 9 columns continue
+    //    with letters, 'a' being the tenth column. In to_moves the other moves are written as
+    //    the column after the prefix of Action::prefix, e.g. '-' for a pop.
+    // The other formats write the columns of a three-dimensional board one after the other as if
+    // they were in a single row.
+
+    fn char_to_token(c: char) -> Result<Option<Token>, PositionError> {
+        match c {
+            'Y' => Result::Ok(Some(Token::YELLOW)),
+            'R' => Result::Ok(Some(Token::RED)),
+            'G' => Result::Ok(Some(Token::GREEN)),
+            'B' => Result::Ok(Some(Token::BLUE)),
+            '_' => Result::Ok(None),
+            _ => Result::Err(PositionError::InvalidCharacter(c)),
         }
     }
 
-    fn step((x, y): Index, dir: &Direction2D) -> Option<Index> {
-        match (
-            Board::linear_step(x, &dir.x, WIDTH),
-            Board::linear_step(y, &dir.y, HEIGHT),
-        ) {
-            (Some(x), Some(y)) => Some((x, y)),
-            _ => None,
+    fn token_to_char(token: &Token) -> char {
+        match token {
+            Token::YELLOW => 'Y',
+            Token::RED => 'R',
+            Token::GREEN => 'G',
+            Token::BLUE => 'B',
         }
     }
 
-    pub fn have_winner_at_column(&self, column: u8) -> bool {
-        for i in 1..=HEIGHT {
-            let row = HEIGHT + 1 - i;
-            if self.tokens.contains_key(&(column, row)) {
-                return self.have_winner_at_index((column, row));
+    fn set_cell(&mut self, x: u8, y: u8, token: Option<Token>) {
+        if let Some(color) = token {
+            if self.occupied() & self.cell_bit(x, y) == 0 {
+                self.toggle_cell(self.cell_index(x, y), &color);
             }
         }
-        false
     }
 
-    fn have_winner_at_index(&self, idx: Index) -> bool {
-        match self.tokens.get(&idx) {
-            Some(color) => {
-                // For every possible direction we count how many contiguous tokens of the same color
-                // are present. To win we have to get at least 5 as the starting point will be counted
-                // in both directions. Not the cleanest way.
+    fn set_blocker(&mut self, x: u8, y: u8) {
+        if self.occupied() & self.cell_bit(x, y) == 0 {
+            self.toggle_blocker(self.cell_index(x, y));
+        }
+    }
 
-                // Check horizontal
-                if (self.count_same_color_in_direction(Some(idx), &Direction2D::left(), color)
-                    + self.count_same_color_in_direction(Some(idx), &Direction2D::right(), color))
-                    >= 5
-                {
-                    return true;
-                }
-                // Check vertical
-                if (self.count_same_color_in_direction(Some(idx), &Direction2D::up(), color)
-                    + self.count_same_color_in_direction(Some(idx), &Direction2D::down(), color))
-                    >= 5
-                {
-                    return true;
-                }
+    // Sets a blocker that is part of the board the game starts from
+    fn place_blocker(&mut self, x: u8, y: u8) {
+        self.set_blocker(x, y);
+        self.start_blockers |= self.blockers & self.cell_bit(x, y);
+    }
 
-                // Check downward
-                if (self.count_same_color_in_direction(Some(idx), &Direction2D::upleft(), color)
-                    + self.count_same_color_in_direction(
-                        Some(idx),
-                        &Direction2D::downright(),
-                        color,
-                    ))
-                    >= 5
-                {
-                    return true;
-                }
+    fn toggle_blocker(&mut self, index: u32) {
+        self.blockers ^= 1 << index;
+        self.hash ^= Board::zobrist_key(index, BLOCKER_PIECE);
+    }
 
-                // Check upward
-                if (self.count_same_color_in_direction(Some(idx), &Direction2D::upright(), color)
-                    + self.count_same_color_in_direction(
-                        Some(idx),
-                        &Direction2D::downleft(),
-                        color,
-                    ))
-                    >= 5
-                {
-                    return true;
+    // Sets a cell from its character in the text formats
+    fn set_char(&mut self, x: u8, y: u8, c: char) -> Result<(), PositionError> {
+        if c == BLOCKER_CHAR && self.config.blockers {
+            self.place_blocker(x, y);
+        } else {
+            match Board::char_to_token(c)? {
+                Some(token) if token.index() >= self.config.players as usize => {
+                    return Result::Err(PositionError::InvalidCharacter(c))
                 }
-
-                false
+                token => self.set_cell(x, y, token),
             }
-            None => false,
         }
+        Result::Ok(())
     }
 
-    fn count_same_color_in_direction(
-        &self,
-        idx: Option<Index>,
-        dir: &Direction2D,
-        color: &Token,
-    ) -> u8 {
-        if let Some(index) = idx {
-            match self.tokens.get(&index) {
-                Some(c) if c == color => {
-                    1 + self.count_same_color_in_direction(Board::step(index, &dir), dir, color)
-                }
-                _ => 0,
-            }
-        } else {
-            0
-        }
+    // Random number for a piece in the cell with the given index, the piece being the index of
+    // a token or BLOCKER_PIECE. It is derived from the index with the splitmix64 mixer, so every
+    // board agrees on it.
+    fn zobrist_key(index: u32, piece: usize) -> u64 {
+        let key = index as u64 * (MAX_PLAYERS as u64 + 1) + piece as u64 + 1;
+        let mut z = key.wrapping_mul(0x9E37_79B9_7F4A_7C15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
     }
 
-    pub fn is_full(&self) -> bool {
-        self.tokens.len() == (WIDTH * HEIGHT) as usize
+    // Adds or removes a token, keeping the hash up to date
+    fn toggle_cell(&mut self, index: u32, color: &Token) {
+        self.positions[color.index()] ^= 1 << index;
+        self.hash ^= Board::zobrist_key(index, color.index());
     }
 
-    pub fn reset(&mut self) {
-        self.tokens.clear();
+    fn compute_hash(&self) -> u64 {
+        let mut hash = 0;
+        for index in 0..128 {
+            if let Some(token) = TOKENS
+                .iter()
+                .find(|token| self.positions[token.index()] & (1 << index) != 0)
+            {
+                hash ^= Board::zobrist_key(index, token.index());
+            } else if self.blockers & (1 << index) != 0 {
+                hash ^= Board::zobrist_key(index, BLOCKER_PIECE);
+            }
+        }
+        hash
     }
-}
 
-#[cfg(test)]
-mod test {
-    use super::*;
+    // Zobrist hash of the position: it only depends on the tokens on the board, not on the order
+    // they were played in, and costs nothing to read.
+    #[allow(dead_code)]
+    pub fn zobrist_hash(&self) -> u64 {
+        self.hash
+    }
 
-    #[test]
-    fn test_winner() {
-        let mut board = Board::new();
-        for i in 1..=WIDTH {
-            assert!(board.have_winner_at_column(i) == false);
+    #[allow(dead_code)]
+    pub fn from_string(value: &str, config: Config) -> Result<Board, PositionError> {
+        let expected = config.columns() as usize * config.height as usize;
+        let found = value.chars().count();
+        if found != expected {
+            return Result::Err(PositionError::WrongLength { expected, found });
         }
-        board
-            .add_token(4, &Token::YELLOW)
-            .expect("could not add token");
-        assert!(board.have_winner_at_column(4) == false);
-        board
-            .add_token(4, &Token::YELLOW)
-            .expect("could not add token");
-        assert!(board.have_winner_at_column(4) == false);
-        board
-            .add_token(4, &Token::YELLOW)
-            .expect("could not add token");
-        assert!(board.have_winner_at_column(4) == false);
-        board
-            .add_token(4, &Token::RED)
-            .expect("could not add token");
-        assert!(board.have_winner_at_column(4) == false);
-        board
-            .add_token(2, &Token::YELLOW)
-            .expect("could not add token");
-        assert!(board.have_winner_at_column(2) == false);
-        board
-            .add_token(2, &Token::YELLOW)
-            .expect("could not add token");
-        assert!(board.have_winner_at_column(2) == false);
-        board
-            .add_token(2, &Token::YELLOW)
-            .expect("could not add token");
-        assert!(board.have_winner_at_column(2) == false);
-        board
-            .add_token(2, &Token::YELLOW)
-            .expect("could not add token");
-        assert!(board.have_winner_at_column(2) == true);
+
+        let mut board = Board::with_config(config);
+        let mut chars = value.chars();
+        for y in 1..=config.height {
+            for x in 1..=config.columns() {
+                if let Some(c) = chars.next() {
+                    board.set_char(x, y, c)?;
+                }
+            }
+        }
+        board.validate()?;
+        Result::Ok(board)
     }
 
-    #[test]
-    fn test_is_full() {
-        let mut board = Board::new();
-        assert!(!board.is_full());
-        for column in 1..=WIDTH {
-            for _ in 1..=HEIGHT {
-                assert!(!board.is_full());
-                board
-                    .add_token(column, &Token::YELLOW)
-                    .expect("could not add token");
+    #[allow(dead_code)]
+    pub fn to_moves(&self) -> String {
+        let mut moves = String::new();
+        for played in self.history.iter() {
+            let action = played.action;
+            if let Some(prefix) = action.prefix() {
+                moves.push(prefix);
+            }
+            if let Some(c) = std::char::from_digit(action.column() as u32, 36) {
+                moves.push(c);
             }
         }
-        assert!(board.is_full());
+        moves
     }
 
-    #[test]
-    fn test_serializing() {
-        let mut board = Board::new();
-        let serialized = board.to_number();
-        assert!(serialized == 0);
-        assert!(Board::from_number(serialized).to_number() == serialized);
-        for column in 1..=WIDTH {
-            for row in 1..=HEIGHT {
-                if (row + column) % 2 == 0 {
-                    board
-                        .add_token(column, &Token::YELLOW)
-                        .expect("could not add token");
-                } else {
-                    board
-                        .add_token(column, &Token::RED)
-                        .expect("could not add token");
+    // Plays the sequence of moves from an empty board, yellow first. The sequence must stop
+    // when a player wins, and the board keeps it as its move history. The moves are only checked
+    // against the board, Game::from_moves also checks them against the rules.
+    #[allow(dead_code)]
+    pub fn from_moves(value: &str, config: Config) -> Result<Board, PositionError> {
+        let mut board = Board::with_config(config);
+        let mut color = Token::YELLOW;
+        for (i, action) in Action::parse_moves(value)?.into_iter().enumerate() {
+            let index = i + 1;
+            if board
+                .positions
+                .iter()
+                .any(|&position| board.has_line(position))
+            {
+                return Result::Err(PositionError::IllegalMove {
+                    index,
+                    reason: "the game is already over",
+                });
+            }
+            match board.play(action, &color) {
+                Ok(true) => (),
+                Ok(false) if action.prefix().is_none() => {
+                    return Result::Err(PositionError::IllegalMove {
+                        index,
+                        reason: "the column is full",
+                    })
                 }
+                Ok(false) => {
+                    return Result::Err(PositionError::IllegalMove {
+                        index,
+                        reason: "the move cannot be made there",
+                    })
+                }
+                Err(reason) => return Result::Err(PositionError::IllegalMove { index, reason }),
             }
+            color = board.next_player(&color);
         }
-        let board_full = Board::from_number(board.to_number());
-        assert!(board_full.is_full());
-        assert!(board_full.to_number() == board.to_number());
-        assert!(board_full.to_number() > 1);
+        Result::Ok(board)
     }
 
-    #[test]
-    fn test_to_string() {
-        let mut board = Board::new();
-        assert!(board.to_string() == String::from("__________________________________________"));
-        board
-            .add_token(1, &Token::YELLOW)
-            .expect("Could not add token");
-        assert!(board.to_string() == String::from("Y_________________________________________"));
-        board
-            .add_token(1, &Token::RED)
-            .expect("Could not add token");
-        assert!(board.to_string() == String::from("YR________________________________________"));
-        board
-            .add_token(2, &Token::RED)
-            .expect("Could not add token");
-        assert!(board.to_string() == String::from("YR____R___________________________________"));
+    #[allow(dead_code)]
+    pub fn to_fen(&self, side_to_move: &Token) -> String {
+        let rows: Vec<String> = (1..=self.config.height)
+            .rev()
+            .map(|y| {
+                let layers: Vec<String> = (1..=self.config.depth)
+                    .map(|z| {
+                        (1..=self.config.width)
+                            .map(|x| self.cell_to_char(self.config.column_at(x, z), y))
+                            .collect()
+                    })
+                    .collect();
+                layers.join("|")
+            })
+            .collect();
+        let mut fen = format!(
+            "{} {} {} {} {}",
+            rows.join("/"),
+            Board::token_to_char(side_to_move),
+            self.token_counts().iter().sum::<u32>(),
+            self.config.connect,
+            self.config.players
+        );
+        if self.config.wrap {
+            fen = format!("{} {}", fen, WRAP_FIELD);
+        }
+        if self.config.blockers {
+            fen = format!("{} {}", fen, BLOCKERS_FIELD);
+        }
+        fen
+    }
+
+    // Parses a position written by to_fen, returning the board and the side to move. The size of
+    // the board comes from the rows, the rest of the configuration from the other fields.
+    #[allow(dead_code)]
+    pub fn from_fen(value: &str) -> Result<(Board, Token), PositionError> {
+        let fields: Vec<&str> = value.split_whitespace().collect();
+        let flags = fields.get(5..).unwrap_or_default();
+        let wrap = flags.contains(&WRAP_FIELD);
+        let blockers = flags.contains(&BLOCKERS_FIELD);
+        if fields.len() < 5 || flags.len() != wrap as usize + blockers as usize {
+            return Result::Err(PositionError::InvalidFormat(
+                "expected the rows, the side to move, the move count, the length of the winning \
+                 line, the number of players and maybe \"wrap\" and \"blockers\"",
+            ));
+        }
+        let (connect, players) = match (fields[3].parse::<u8>(), fields[4].parse::<u8>()) {
+            (Ok(connect), Ok(players)) => (connect, players),
+            _ => {
+                return Result::Err(PositionError::InvalidFormat(
+                    "the length of the winning line and the number of players must be numbers",
+                ))
+            }
+        };
+
+        let rows: Vec<&str> = fields[0].split('/').collect();
+        let width = rows[0].chars().take_while(|&c| c != '|').count();
+        let depth = rows[0].split('|').count();
+        if rows.iter().any(|row| {
+            row.split('|').count() != depth
+                || row.split('|').any(|layer| layer.chars().count() != width)
+        }) {
+            return Result::Err(PositionError::InvalidFormat(
+                "all rows must have the same length",
+            ));
+        }
+        if width > u8::MAX as usize || depth > u8::MAX as usize || rows.len() > u8::MAX as usize {
+            return Result::Err(PositionError::InvalidSize("the board is too large"));
+        }
+        let mut side_chars = fields[1].chars();
+        let side_to_move = match (
+            side_chars.next().map(Board::char_to_token),
+            side_chars.next(),
+        ) {
+            (Some(Ok(Some(token))), None) => token,
+            _ => {
+                return Result::Err(PositionError::InvalidFormat(
+                    "the side to move must be Y, R, G or B",
+                ))
+            }
+        };
+        let config = Config::new(width as u8, rows.len() as u8, connect)
+            .and_then(|config| config.with_depth(depth as u8))
+            .and_then(|config| config.with_wrap(wrap))
+            .and_then(|config| config.with_players(players))
+            .and_then(|config| config.with_blockers(blockers))
+            .map_err(PositionError::InvalidSize)?;
+
+        let mut board = Board::with_config(config);
+        for (y, row) in (1..=config.height).rev().zip(rows.iter()) {
+            let cells = row.chars().filter(|&c| c != '|');
+            for (x, c) in (1..=config.columns()).zip(cells) {
+                board.set_char(x, y, c)?;
+            }
+        }
+        board.validate()?;
+
+        // The counts go down in turn order, so the first player with fewer tokens than yellow is
+        // to move, or yellow when everyone has played as many
+        let counts = board.token_counts();
+        let expected_side = counts
+            .iter()
+            .position(|&count| count < counts[0])
+            .map_or(Token::YELLOW, |index| TOKENS[index].clone());
+        if side_to_move != expected_side {
+            return Result::Err(PositionError::WrongSideToMove);
+        }
+
+        let expected = counts.iter().sum();
+        match fields[2].parse::<u32>() {
+            Ok(found) if found == expected => Result::Ok((board, side_to_move)),
+            Ok(found) => Result::Err(PositionError::MoveCountMismatch { expected, found }),
+            Err(_) => Result::Err(PositionError::InvalidFormat(
+                "the move count must be a number",
+            )),
+        }
+    }
+
+    // Number of tokens of each player in the game
+    fn token_counts(&self) -> Vec<u32> {
+        self.positions[..self.config.players as usize]
+            .iter()
+            .map(|position| position.count_ones())
+            .collect()
+    }
+
+    // Blockers are placed rather than dropped, so only tokens need something below them
+    fn validate(&self) -> Result<(), PositionError> {
+        let occupied = self.occupied();
+        let tokens = occupied & !self.blockers;
+        for x in 1..=self.config.columns() {
+            for y in 2..=self.config.height {
+                if tokens & self.cell_bit(x, y) != 0 && occupied & self.cell_bit(x, y - 1) == 0 {
+                    return Result::Err(PositionError::FloatingToken { column: x, row: y });
+                }
+            }
+        }
+
+        let counts = self.token_counts();
+        let most = *counts.iter().max().unwrap_or(&0);
+        let fewest = *counts.iter().min().unwrap_or(&0);
+        if most > fewest + 1 {
+            return Result::Err(PositionError::TokenCountImbalance { most, fewest });
+        }
+        if counts.windows(2).any(|pair| pair[0] < pair[1]) {
+            return Result::Err(PositionError::OutOfTurn);
+        }
+
+        let winners = self
+            .positions
+            .iter()
+            .filter(|&&position| self.has_line(position))
+            .count();
+        if winners > 1 {
+            return Result::Err(PositionError::BothPlayersWin);
+        }
+        Result::Ok(())
+    }
+
+    pub fn is_legal(&self, column: u8) -> bool {
+        (1..=self.config.columns()).contains(&column)
+            && self.column_height(column) < self.config.height
+    }
+
+    // Columns that are not full, in the order they are numbered
+    pub fn legal_moves(&self) -> Vec<u8> {
+        (1..=self.config.columns())
+            .filter(|&column| self.is_legal(column))
+            .collect()
+    }
+
+    // Sorts the moves by the distance of their column from the center, keeping their order on
+    // ties. Central columns take part in more lines, so searching them first finds the good moves
+    // sooner.
+    pub fn sort_center_first(&self, moves: &mut [Action]) {
+        moves.sort_by_key(|action| self.center_distance(action.column()));
+    }
+
+    // Sorts first the drops that complete a line for the player, then the ones that take the cell
+    // the next player would complete a line in, and last the ones that give the next player such a
+    // cell just above, keeping the order of the other moves. The search finds the forced moves
+    // sooner this way.
+    pub fn sort_forcing_first(&self, moves: &mut [Action], token: &Token, next: &Token) {
+        let wins = self.threat_cells(self.position_of(token));
+        let losses = self.threat_cells(self.position_of(next));
+        moves.sort_by_key(|action| match action {
+            Action::DROP(column) => {
+                let row = self.column_height(*column) + 1;
+                if wins & self.cell_bit(*column, row) != 0 {
+                    0
+                } else if losses & self.cell_bit(*column, row) != 0 {
+                    1
+                } else if row < self.config.height && losses & self.cell_bit(*column, row + 1) != 0
+                {
+                    3
+                } else {
+                    2
+                }
+            }
+            _ => 2,
+        });
+    }
+
+    // How far the column is from the center of the board, in half columns
+    pub fn center_distance(&self, column: u8) -> i16 {
+        let (x, z) = self.config.column_place(column);
+        (2 * x as i16 - self.config.width as i16 - 1).abs()
+            + (2 * z as i16 - self.config.depth as i16 - 1).abs()
+    }
+
+    pub fn add_token(&mut self, column: u8, color: &Token) -> Result<bool, &str> {
+        self.play(Action::DROP(column), color)
+    }
+
+    // Whether the token at the bottom of the column belongs to the player. Blockers cannot fall,
+    // so a column holding one cannot be popped.
+    pub fn can_pop(&self, column: u8, color: &Token) -> bool {
+        self.get_color_at_cell(column, 1) == Some(color) && !self.has_blocker(column)
+    }
+
+    // Removes the player's token from the bottom of the column, the tokens above it fall down.
+    // Returns false if the bottom of the column is empty or holds a token of the opponent, or if
+    // the column holds a blocker.
+    pub fn pop_token(&mut self, column: u8, color: &Token) -> Result<bool, &str> {
+        self.play(Action::POP(column), color)
+    }
+
+    fn has_blocker(&self, column: u8) -> bool {
+        (1..=self.config.height).any(|y| self.is_blocker(column, y))
+    }
+
+    // Whether the player can make the move, in a column inside the board. Like pops, anvils and
+    // bombs cannot move blockers.
+    pub fn can_play(&self, action: Action, color: &Token) -> bool {
+        let column = action.column();
+        match action {
+            Action::DROP(_) | Action::EXTRA(_) => self.is_legal(column),
+            Action::WALL(_) => self.config.blockers && self.is_legal(column),
+            Action::POP(_) => self.can_pop(column, color),
+            Action::ANVIL(_) => !self.has_blocker(column),
+            Action::BOMB(_) => match self.get_color_at_cell(column, 1) {
+                Some(bottom) => bottom != color && !self.has_blocker(column),
+                None => false,
+            },
+        }
+    }
+
+    // Makes the move for the player, returning false if it cannot be made there
+    pub fn play(&mut self, action: Action, color: &Token) -> Result<bool, &'static str> {
+        if !(1..=self.config.columns()).contains(&action.column()) {
+            return Result::Err("out of bounds");
+        }
+        if !self.can_play(action, color) {
+            return Result::Ok(false);
+        }
+
+        self.apply(action, color);
+        self.undone.clear();
+        Result::Ok(true)
+    }
+
+    fn apply(&mut self, action: Action, color: &Token) {
+        let hash = self.hash;
+        let column = action.column();
+        let top = self.column_height(column) + 1;
+        let removed = match action {
+            Action::DROP(_) | Action::EXTRA(_) => {
+                self.toggle_cell(self.cell_index(column, top), color);
+                Vec::new()
+            }
+            Action::WALL(_) => {
+                self.set_blocker(column, top);
+                Vec::new()
+            }
+            Action::POP(_) | Action::BOMB(_) => self.remove_bottom_token(column),
+            Action::ANVIL(_) => {
+                let removed: Vec<Token> = (1..top)
+                    .filter_map(|y| self.get_color_at_cell(column, y).cloned())
+                    .collect();
+                for (y, token) in (1..).zip(removed.iter()) {
+                    self.toggle_cell(self.cell_index(column, y), token);
+                }
+                self.toggle_cell(self.cell_index(column, 1), color);
+                removed
+            }
+        };
+        self.history.push(PlayedMove {
+            action,
+            token: color.clone(),
+            removed,
+            hash,
+        });
+    }
+
+    // Takes the token at the bottom of the column off the board, returning it
+    fn remove_bottom_token(&mut self, column: u8) -> Vec<Token> {
+        let removed: Vec<Token> = self
+            .get_color_at_cell(column, 1)
+            .cloned()
+            .into_iter()
+            .collect();
+        for token in removed.iter() {
+            self.toggle_cell(self.cell_index(column, 1), token);
+        }
+        for y in 2..=self.column_height(column) {
+            self.move_cell(column, y, y - 1);
+        }
+        removed
+    }
+
+    fn move_cell(&mut self, column: u8, from: u8, to: u8) {
+        if let Some(color) = self.get_color_at_cell(column, from).cloned() {
+            self.toggle_cell(self.cell_index(column, from), &color);
+            self.toggle_cell(self.cell_index(column, to), &color);
+        }
+    }
+
+    // Takes back the last move, returning it
+    pub fn undo_move(&mut self) -> Option<Action> {
+        let played = self.history.pop()?;
+        let column = played.action.column();
+        let top = self.column_height(column);
+        match played.action {
+            Action::DROP(_) | Action::EXTRA(_) => {
+                self.toggle_cell(self.cell_index(column, top), &played.token)
+            }
+            Action::WALL(_) => self.toggle_blocker(self.cell_index(column, top)),
+            Action::POP(_) | Action::BOMB(_) => {
+                for y in (1..=top).rev() {
+                    self.move_cell(column, y, y + 1);
+                }
+                for token in played.removed.iter() {
+                    self.toggle_cell(self.cell_index(column, 1), token);
+                }
+            }
+            Action::ANVIL(_) => {
+                self.toggle_cell(self.cell_index(column, 1), &played.token);
+                for (y, token) in (1..).zip(played.removed.iter()) {
+                    self.toggle_cell(self.cell_index(column, y), token);
+                }
+            }
+        }
+        let action = played.action;
+        self.undone.push(played);
+        Some(action)
+    }
+
+    // Plays again the last move taken back by undo_move, returning it
+    pub fn redo_move(&mut self) -> Option<Action> {
+        let played = self.undone.pop()?;
+        self.apply(played.action, &played.token);
+        Some(played.action)
+    }
+
+    // Moves played so far, oldest first. Positions built with from_number have no history.
+    pub fn moves(&self) -> Vec<Action> {
+        self.history.iter().map(|played| played.action).collect()
+    }
+
+    // Moves played so far by the player, oldest first
+    pub fn moves_of(&self, token: &Token) -> Vec<Action> {
+        self.history
+            .iter()
+            .filter(|played| played.token == *token)
+            .map(|played| played.action)
+            .collect()
+    }
+
+    // Number of times the position has been on the board with the same player to move, this
+    // time included
+    pub fn repetitions(&self, to_move: &Token) -> usize {
+        let earlier = self
+            .history
+            .iter()
+            .filter(|played| played.hash == self.hash && played.token == *to_move)
+            .count();
+        earlier + 1
+    }
+
+    // The last move played and the player who played it
+    pub fn last_move(&self) -> Option<(Action, Token)> {
+        self.history
+            .last()
+            .map(|played| (played.action, played.token.clone()))
+    }
+
+    // Places a blocker in the cell, wherever it is: the cells below an empty one can no longer
+    // be played. Returns false if the cell is already taken.
+    pub fn add_blocker(&mut self, x: u8, y: u8) -> Result<bool, &str> {
+        if !self.config.blockers {
+            return Result::Err("the board does not allow blockers");
+        }
+        if !(1..=self.config.columns()).contains(&x) || !(1..=self.config.height).contains(&y) {
+            return Result::Err("out of bounds");
+        }
+        if self.occupied() & self.cell_bit(x, y) != 0 {
+            return Result::Ok(false);
+        }
+        self.place_blocker(x, y);
+        Result::Ok(true)
+    }
+
+    // Drops blockers in random columns, as if they had been played. Stops early when the board
+    // is full.
+    pub fn add_random_blockers(&mut self, count: u8) -> Result<(), &str> {
+        if !self.config.blockers {
+            return Result::Err("the board does not allow blockers");
+        }
+        self.random_blockers = self.random_blockers.saturating_add(count);
+        self.drop_random_blockers(count);
+        Result::Ok(())
+    }
+
+    // Drops blockers on top of random columns, as long as there is room left
+    fn drop_random_blockers(&mut self, count: u8) {
+        let mut rng = rand::thread_rng();
+        for _ in 0..count {
+            match self.legal_moves().choose(&mut rng) {
+                Some(&column) => self.set_blocker(column, self.column_height(column) + 1),
+                None => break,
+            }
+        }
+    }
+
+    pub fn is_blocker(&self, x: u8, y: u8) -> bool {
+        (1..=self.config.columns()).contains(&x)
+            && (1..=self.config.height).contains(&y)
+            && self.blockers & self.cell_bit(x, y) != 0
+    }
+
+    pub fn get_color_at_cell(&self, x: u8, y: u8) -> Option<&Token> {
+        if !(1..=self.config.columns()).contains(&x) || !(1..=self.config.height).contains(&y) {
+            return None;
+        }
+        let bit = self.cell_bit(x, y);
+        TOKENS
+            .iter()
+            .find(|token| self.positions[token.index()] & bit != 0)
+    }
+
+    // Moves every cell of the position up the bitboard by the given number of bits, or down for
+    // a negative amount. Bits leaving a flat board are lost, on a cylinder the columns leaving
+    // one side come back on the other one.
+    fn shift_cells(&self, position: u128, amount: i32) -> u128 {
+        if !self.config.wrap {
+            return if amount >= 0 {
+                position.checked_shl(amount as u32).unwrap_or(0)
+            } else {
+                position.checked_shr(amount.unsigned_abs()).unwrap_or(0)
+            };
+        }
+        let total_bits = self.config.total_bits();
+        let board_mask = u128::MAX.checked_shr(128 - total_bits).unwrap_or(0);
+        let amount = amount.rem_euclid(total_bits as i32) as u32;
+        if amount == 0 {
+            return position;
+        }
+        ((position << amount) | (position >> (total_bits - amount))) & board_mask
+    }
+
+    // Index of the cell the given number of bits further along a line, if it is on the bitboard
+    fn step_index(&self, index: u32, amount: i32) -> Option<u32> {
+        if self.config.wrap {
+            let total_bits = self.config.total_bits() as i32;
+            Some((index as i32 + amount).rem_euclid(total_bits) as u32)
+        } else {
+            let next = index as i32 + amount;
+            if (0..MAX_BITS as i32).contains(&next) {
+                Some(next as u32)
+            } else {
+                None
+            }
+        }
+    }
+
+    // Returns the mask of all the cells that are part of a winning line in the given position,
+    // along the direction with the given shift.
+    fn line_cells(&self, position: u128, shift: u32) -> u128 {
+        let connect = self.config.connect as u32;
+        // A bit survives in starts only if the following connect - 1 cells are also set.
+        // Narrow boards can have shifts past the size of the bitboard, which just clear it.
+        let mut starts = position;
+        for i in 1..connect {
+            starts &= self.shift_cells(position, -((i * shift) as i32));
+        }
+        let mut cells = 0;
+        for i in 0..connect {
+            cells |= self.shift_cells(starts, (i * shift) as i32);
+        }
+        cells
+    }
+
+    // Mask of the cells on the board, without the separator bit on top of each column
+    fn cells_mask(&self) -> u128 {
+        let column_mask = (1u128 << self.config.height) - 1;
+        let mut mask = 0;
+        for column in 1..=self.config.columns() {
+            mask |= column_mask << self.config.column_start(column);
+        }
+        mask
+    }
+
+    fn position_of(&self, token: &Token) -> u128 {
+        self.positions[token.index()]
+    }
+
+    // Mask of the empty cells that would complete a line for the given position. For every
+    // direction, a cell completes a line when the runs of tokens of the position on both sides of
+    // it add up to connect - 1, the runs being grown one cell at a time.
+    fn threat_cells(&self, position: u128) -> u128 {
+        let connect = self.config.connect as usize;
+        let mut threats = 0;
+        for &shift in self.config.directions().iter() {
+            // Cells with at least i tokens just before and just after them, from 0 tokens
+            let mut before = [!0u128; MAX_CONNECT as usize];
+            let mut after = [!0u128; MAX_CONNECT as usize];
+            for i in 1..connect {
+                let amount = (i as u32 * shift) as i32;
+                before[i] = before[i - 1] & self.shift_cells(position, amount);
+                after[i] = after[i - 1] & self.shift_cells(position, -amount);
+            }
+            for i in 0..connect {
+                threats |= before[i] & after[connect - 1 - i];
+            }
+        }
+        // Lines crossing the top of a column or the sides of the board go through the cells
+        // outside of it, so they are dropped here
+        threats & self.cells_mask() & !self.occupied()
+    }
+
+    fn playable_cells(&self) -> u128 {
+        let mut cells = 0;
+        for column in self.legal_moves() {
+            cells |= self.cell_bit(column, self.column_height(column) + 1);
+        }
+        cells
+    }
+
+    // Columns where the player would complete a line right now
+    #[allow(dead_code)]
+    pub fn winning_moves(&self, token: &Token) -> Vec<u8> {
+        let threats = self.threat_cells(self.position_of(token)) & self.playable_cells();
+        self.legal_moves()
+            .into_iter()
+            .filter(|&column| threats & self.cell_bit(column, self.column_height(column) + 1) != 0)
+            .collect()
+    }
+
+    // Every empty cell that would complete a line for the player, playable now or not, column by
+    // column from the bottom
+    #[allow(dead_code)]
+    pub fn threats(&self, token: &Token) -> Vec<Threat> {
+        let threats = self.threat_cells(self.position_of(token));
+        (0..128)
+            .filter(|&index| threats & (1 << index) != 0)
+            .map(|index| {
+                let (column, row) = self.index_to_cell(index);
+                let parity = if row % 2 == 1 {
+                    Parity::ODD
+                } else {
+                    Parity::EVEN
+                };
+                Threat {
+                    column,
+                    row,
+                    parity,
+                }
+            })
+            .collect()
+    }
+
+    // Legal columns where a token of the player would let the next player win on top of it
+    #[allow(dead_code)]
+    pub fn unsafe_columns(&self, token: &Token) -> Vec<u8> {
+        let opponent_threats = self.threat_cells(self.position_of(&self.next_player(token)));
+        self.legal_moves()
+            .into_iter()
+            .filter(|&column| {
+                let row = self.column_height(column) + 2;
+                row <= self.config.height && opponent_threats & self.cell_bit(column, row) != 0
+            })
+            .collect()
+    }
+
+    // Lines of connect cells the player could still complete, by how many of their tokens they
+    // hold: the number at index k counts the lines with k tokens of the player and every other
+    // cell empty.
+    pub fn open_windows(&self, token: &Token) -> Vec<u32> {
+        let connect = self.config.connect as u32;
+        let position = self.position_of(token);
+        let free = self.cells_mask() & !(self.occupied() & !position);
+        let mut counts = vec![0; connect as usize + 1];
+        for &shift in self.config.directions().iter() {
+            // Same as in line_cells, the start of every line of free cells
+            let mut starts = free;
+            for i in 1..connect {
+                starts &= self.shift_cells(free, -((i * shift) as i32));
+            }
+            // Tokens of the player in the line from each start, counted in binary with one bit
+            // of the count in each mask, connect being at most 6
+            let mut sums = [0u128; 3];
+            for i in 0..connect {
+                let mut carry = self.shift_cells(position, -((i * shift) as i32)) & starts;
+                for sum in sums.iter_mut() {
+                    let next = *sum & carry;
+                    *sum ^= carry;
+                    carry = next;
+                }
+            }
+            for (tokens, count) in counts.iter_mut().enumerate() {
+                let mut lines = starts;
+                for (bit, sum) in sums.iter().enumerate() {
+                    lines &= if tokens >> bit & 1 == 1 { *sum } else { !*sum };
+                }
+                *count += lines.count_ones();
+            }
+        }
+        counts
+    }
+
+    // Numbers of empty cells that would complete a line for the player, on odd and on even rows
+    pub fn threat_counts(&self, token: &Token) -> (u32, u32) {
+        let column_rows = (0..self.config.height)
+            .step_by(2)
+            .fold(0u128, |rows, y| rows | 1 << y);
+        let mut odd_rows = 0;
+        for column in 1..=self.config.columns() {
+            odd_rows |= column_rows << self.config.column_start(column);
+        }
+        let threats = self.threat_cells(self.position_of(token));
+        (
+            (threats & odd_rows).count_ones(),
+            (threats & !odd_rows).count_ones(),
+        )
+    }
+
+    // How much closer to the center than the corner columns the tokens of the player are, in
+    // half columns, added over all the tokens
+    pub fn center_closeness(&self, token: &Token) -> u32 {
+        let column_mask = (1u128 << self.config.height) - 1;
+        let edge_distance = self.center_distance(1);
+        let position = self.position_of(token);
+        (1..=self.config.columns())
+            .map(|column| {
+                let tokens = (position >> self.config.column_start(column)) & column_mask;
+                (edge_distance - self.center_distance(column)) as u32 * tokens.count_ones()
+            })
+            .sum()
+    }
+
+    // Number of cells still empty, cells below a blocker included
+    pub fn empty_cells(&self) -> u32 {
+        (self.cells_mask() & !self.occupied()).count_ones()
+    }
+
+    fn has_line(&self, position: u128) -> bool {
+        self.config
+            .directions()
+            .iter()
+            .any(|&shift| self.line_cells(position, shift) != 0)
+    }
+
+    // Walks along the direction with the given shift, collecting the contiguous cells of the
+    // mask that go through the starting index.
+    // On a cylinder a full row goes all the way around, so the walk stops where it started.
+    fn run_through(&self, cells: u128, index: u32, shift: u32) -> Vec<(u8, u8)> {
+        let contains = |i: &u32| cells & (1 << i) != 0;
+        let mut start = index;
+        while let Some(previous) = self.step_index(start, -(shift as i32)).filter(contains) {
+            if previous == index {
+                break;
+            }
+            start = previous;
+        }
+        let mut run = vec![self.index_to_cell(start)];
+        let mut current = start;
+        while let Some(next) = self.step_index(current, shift as i32).filter(contains) {
+            if next == start {
+                break;
+            }
+            run.push(self.index_to_cell(next));
+            current = next;
+        }
+        run
+    }
+
+    // Returns who won with the token on top of the column, if anyone, and every line going
+    // through it. Each line lists all the contiguous cells, so it can be longer than needed.
+    pub fn winner_at_column(&self, column: u8) -> Option<Win> {
+        if !(1..=self.config.columns()).contains(&column) {
+            return None;
+        }
+        match self.column_height(column) {
+            0 => None,
+            height => self.winner_at_cell(column, height),
+        }
+    }
+
+    // Same as winner_at_column, for the token in any cell of the board
+    pub fn winner_at_cell(&self, x: u8, y: u8) -> Option<Win> {
+        let token = self.get_color_at_cell(x, y)?.clone();
+        let position = self.position_of(&token);
+        let index = self.cell_index(x, y);
+        let bit = 1 << index;
+
+        let mut lines = Vec::new();
+        for &shift in self.config.directions().iter() {
+            let cells = self.line_cells(position, shift);
+            if cells & bit != 0 {
+                lines.push(self.run_through(cells, index, shift));
+            }
+        }
+
+        if lines.is_empty() {
+            None
+        } else {
+            Some(Win { token, lines })
+        }
+    }
+
+    // Every line of the player anywhere on the board, as a win. After a pop the tokens of a whole
+    // column move, so lines can appear away from the column played and for both players at once.
+    pub fn winner(&self, token: &Token) -> Option<Win> {
+        let position = self.position_of(token);
+        let mut lines = Vec::new();
+        for &shift in self.config.directions().iter() {
+            let cells = self.line_cells(position, shift);
+            if cells == 0 {
+                continue;
+            }
+            let mut covered = 0u128;
+            for index in 0..MAX_BITS as u32 {
+                if cells & !covered & (1 << index) != 0 {
+                    let run = self.run_through(cells, index, shift);
+                    for &(x, y) in run.iter() {
+                        covered |= self.cell_bit(x, y);
+                    }
+                    lines.push(run);
+                }
+            }
+        }
+
+        if lines.is_empty() {
+            None
+        } else {
+            Some(Win {
+                token: token.clone(),
+                lines,
+            })
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn have_winner_at_column(&self, column: u8) -> bool {
+        self.winner_at_column(column).is_some()
+    }
+
+    // Whether no column can take another token. Cells below a blocker can stay empty.
+    pub fn is_full(&self) -> bool {
+        self.legal_moves().is_empty()
+    }
+
+    // Takes every token off the board. The blockers placed before the game stay, the walls go
+    // and the random blockers are dropped again in new columns.
+    pub fn reset(&mut self) {
+        self.positions = [0; MAX_PLAYERS as usize];
+        self.blockers = self.start_blockers;
+        self.hash = self.compute_hash();
+        self.history.clear();
+        self.undone.clear();
+        self.drop_random_blockers(self.random_blockers);
+    }
+}
+
+impl fmt::Display for Board {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for y in 1..=self.config.height {
+            for x in 1..=self.config.columns() {
+                write!(f, "{}", self.cell_to_char(x, y))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_winner() {
+        let mut board = Board::new();
+        for i in 1..=board.width() {
+            assert!(!board.have_winner_at_column(i));
+        }
+        board
+            .add_token(4, &Token::YELLOW)
+            .expect("could not add token");
+        assert!(!board.have_winner_at_column(4));
+        board
+            .add_token(4, &Token::YELLOW)
+            .expect("could not add token");
+        assert!(!board.have_winner_at_column(4));
+        board
+            .add_token(4, &Token::YELLOW)
+            .expect("could not add token");
+        assert!(!board.have_winner_at_column(4));
+        board
+            .add_token(4, &Token::RED)
+            .expect("could not add token");
+        assert!(!board.have_winner_at_column(4));
+        board
+            .add_token(2, &Token::YELLOW)
+            .expect("could not add token");
+        assert!(!board.have_winner_at_column(2));
+        board
+            .add_token(2, &Token::YELLOW)
+            .expect("could not add token");
+        assert!(!board.have_winner_at_column(2));
+        board
+            .add_token(2, &Token::YELLOW)
+            .expect("could not add token");
+        assert!(!board.have_winner_at_column(2));
+        board
+            .add_token(2, &Token::YELLOW)
+            .expect("could not add token");
+        assert!(board.have_winner_at_column(2));
+    }
+
+    #[test]
+    fn test_winner_diagonal() {
+        let mut board = Board::new();
+        for (column, reds) in [(1, 0), (2, 1), (3, 2), (4, 3)].iter() {
+            for _ in 0..*reds {
+                board
+                    .add_token(*column, &Token::RED)
+                    .expect("could not add token");
+            }
+            board
+                .add_token(*column, &Token::YELLOW)
+                .expect("could not add token");
+        }
+        assert!(board.have_winner_at_column(1));
+        assert!(board.have_winner_at_column(4));
+        assert!(!board.have_winner_at_column(5));
+    }
+
+    #[test]
+    fn test_config() {
+        assert!(Config::new(8, 7, 4).is_ok());
+        assert!(Config::new(9, 7, 4).is_ok());
+        assert!(Config::new(6, 5, 4).is_ok());
+        assert!(Config::new(0, 6, 4).is_err());
+        assert!(Config::new(7, 0, 4).is_err());
+        assert!(Config::new(16, 8, 4).is_err());
+        assert!(Config::new(2, 70, 4).is_err());
+        // Boards too large to be written as a number can still be played
+        let large = Config::new(9, 9, 4).expect("invalid config");
+        assert!(Board::with_config(large).to_number() == Err(PositionError::TooManyCells));
+        assert!(Config::new(2, 60, 4).is_ok());
+        // Every column has a digit in the move notation
+        let widest = Config::new(35, 2, 4).expect("invalid config");
+        assert!(Board::from_moves("z", widest).map(|board| board.to_moves()) == Ok("z".into()));
+        assert!(Config::new(36, 2, 4).is_err());
+        assert!(Config::new(9, 1, 3)
+            .and_then(|config| config.with_depth(4))
+            .is_err());
+        assert!(Config::new(7, 6, 2).is_err());
+        assert!(Config::new(7, 6, 7).is_err());
+    }
+
+    #[test]
+    fn test_custom_size() {
+        let mut board = Board::with_config(Config::new(9, 7, 4).expect("invalid config"));
+        for column in 6..=8 {
+            board
+                .add_token(column, &Token::RED)
+                .expect("could not add token");
+            assert!(!board.have_winner_at_column(column));
+        }
+        board
+            .add_token(9, &Token::RED)
+            .expect("could not add token");
+        assert!(board.have_winner_at_column(9));
+
+        for _ in 1..=7 {
+            assert!(board.add_token(1, &Token::YELLOW) == Ok(true));
+        }
+        assert!(board.get_color_at_cell(1, 7) == Some(&Token::YELLOW));
+        assert!(board.add_token(1, &Token::YELLOW) == Ok(false));
+        assert!(board.add_token(10, &Token::YELLOW).is_err());
+
+        let copy = Board::from_number(
+            board.to_number().expect("could not serialize"),
+            board.config(),
+        );
+        assert!(copy.to_number() == board.to_number());
+        assert!(copy.have_winner_at_column(9));
+    }
+
+    #[test]
+    fn test_connect_length() {
+        let mut connect_three = Board::with_config(Config::new(4, 4, 3).expect("invalid config"));
+        for column in 1..=2 {
+            connect_three
+                .add_token(column, &Token::YELLOW)
+                .expect("could not add token");
+            assert!(!connect_three.have_winner_at_column(column));
+        }
+        connect_three
+            .add_token(3, &Token::YELLOW)
+            .expect("could not add token");
+        assert!(connect_three.have_winner_at_column(3));
+
+        let mut connect_six = Board::with_config(Config::new(7, 6, 6).expect("invalid config"));
+        for column in 1..=5 {
+            connect_six
+                .add_token(column, &Token::RED)
+                .expect("could not add token");
+        }
+        for row in 1..=5 {
+            connect_six
+                .add_token(7, &Token::RED)
+                .expect("could not add token");
+            assert!(!connect_six.have_winner_at_column(7), "row {}", row);
+        }
+        assert!(!connect_six.have_winner_at_column(5));
+        connect_six
+            .add_token(6, &Token::RED)
+            .expect("could not add token");
+        assert!(connect_six.have_winner_at_column(6));
+        assert!(connect_six.have_winner_at_column(1));
+        connect_six
+            .add_token(7, &Token::RED)
+            .expect("could not add token");
+        assert!(connect_six.have_winner_at_column(7));
+    }
+
+    #[test]
+    fn test_undo_redo() {
+        let mut board = Board::new();
+        assert!(board.undo_move().is_none());
+        assert!(board.redo_move().is_none());
+
+        let empty = board.to_number();
+        board
+            .add_token(4, &Token::YELLOW)
+            .expect("could not add token");
+        board
+            .add_token(4, &Token::RED)
+            .expect("could not add token");
+        board
+            .add_token(2, &Token::YELLOW)
+            .expect("could not add token");
+        assert!(board.to_moves() == "442");
+        let played = board.to_number();
+
+        assert!(board.undo_move() == Some(Action::DROP(2)));
+        assert!(board.undo_move() == Some(Action::DROP(4)));
+        assert!(board.get_color_at_cell(4, 2).is_none());
+        assert!(board.get_color_at_cell(4, 1) == Some(&Token::YELLOW));
+        assert!(board.to_moves() == "4");
+        assert!(board.undo_move() == Some(Action::DROP(4)));
+        assert!(board.undo_move().is_none());
+        assert!(board.to_number() == empty);
+
+        assert!(board.redo_move() == Some(Action::DROP(4)));
+        assert!(board.redo_move() == Some(Action::DROP(4)));
+        assert!(board.redo_move() == Some(Action::DROP(2)));
+        assert!(board.redo_move().is_none());
+        assert!(board.to_number() == played);
+        assert!(board.to_moves() == "442");
+
+        // A new move discards the moves that could be redone
+        board.undo_move();
+        board
+            .add_token(1, &Token::YELLOW)
+            .expect("could not add token");
+        assert!(board.redo_move().is_none());
+        assert!(board.to_moves() == "441");
+
+        board.reset();
+        assert!(board.moves().is_empty());
+        assert!(board.undo_move().is_none());
+    }
+
+    fn play(board: &mut Board, moves: &[(u8, Token)]) {
+        for (column, token) in moves.iter() {
+            board
+                .add_token(*column, token)
+                .expect("could not add token");
+        }
+    }
+
+    #[test]
+    fn test_winning_lines() {
+        let mut board = Board::new();
+        assert!(board.winner_at_column(1).is_none());
+        assert!(board.winner_at_column(0).is_none());
+
+        play(
+            &mut board,
+            &[
+                (1, Token::YELLOW),
+                (2, Token::YELLOW),
+                (3, Token::YELLOW),
+                (5, Token::YELLOW),
+                (4, Token::YELLOW),
+            ],
+        );
+        let win = board.winner_at_column(4).expect("no winner");
+        assert!(win.token == Token::YELLOW);
+        assert!(win.lines == vec![vec![(1, 1), (2, 1), (3, 1), (4, 1), (5, 1)]]);
+        assert!(board.winner_at_column(5) == board.winner_at_column(1));
+
+        let mut board = Board::new();
+        play(
+            &mut board,
+            &[
+                (2, Token::RED),
+                (3, Token::RED),
+                (3, Token::RED),
+                (4, Token::RED),
+                (4, Token::RED),
+                (4, Token::RED),
+                (1, Token::YELLOW),
+                (2, Token::YELLOW),
+                (3, Token::YELLOW),
+            ],
+        );
+        assert!(board.winner_at_column(3).is_none());
+        play(&mut board, &[(4, Token::YELLOW)]);
+        let win = board.winner_at_column(4).expect("no winner");
+        assert!(win.token == Token::YELLOW);
+        assert!(win.lines == vec![vec![(1, 1), (2, 2), (3, 3), (4, 4)]]);
+    }
+
+    #[test]
+    fn test_double_win() {
+        let mut board = Board::new();
+        for column in 1..=3 {
+            play(
+                &mut board,
+                &[
+                    (column, Token::RED),
+                    (column, Token::RED),
+                    (column, Token::RED),
+                ],
+            );
+            play(&mut board, &[(column, Token::YELLOW)]);
+        }
+        play(
+            &mut board,
+            &[(4, Token::YELLOW), (4, Token::YELLOW), (4, Token::YELLOW)],
+        );
+        assert!(board.winner_at_column(4).is_none());
+
+        play(&mut board, &[(4, Token::YELLOW)]);
+        let win = board.winner_at_column(4).expect("no winner");
+        assert!(win.token == Token::YELLOW);
+        assert!(win.lines.len() == 2);
+        assert!(win.lines.contains(&vec![(4, 1), (4, 2), (4, 3), (4, 4)]));
+        assert!(win.lines.contains(&vec![(1, 4), (2, 4), (3, 4), (4, 4)]));
+    }
+
+    // Every possible winning line on the board, as cells
+    fn all_lines(config: Config) -> Vec<Vec<(u8, u8)>> {
+        let mut lines = Vec::new();
+        let connect = config.connect as i16;
+        let width = config.width as i16;
+        let mut directions = vec![(1, 0, 0), (0, 1, 0), (1, 1, 0), (1, -1, 0)];
+        if config.depth > 1 {
+            for dx in -1..=1 {
+                for dy in -1..=1 {
+                    directions.push((dx, dy, 1));
+                }
+            }
+        }
+        for &(dx, dy, dz) in directions.iter() {
+            for x in 1..=config.width as i16 {
+                for y in 1..=config.height as i16 {
+                    for z in 1..=config.depth as i16 {
+                        let line: Vec<(i16, i16, i16)> = (0..connect)
+                            .map(|i| {
+                                if config.wrap {
+                                    ((x - 1 + i * dx) % width + 1, y + i * dy, z)
+                                } else {
+                                    (x + i * dx, y + i * dy, z + i * dz)
+                                }
+                            })
+                            .collect();
+                        if line.iter().all(|&(x, y, z)| {
+                            x >= 1
+                                && x <= config.width as i16
+                                && y >= 1
+                                && y <= config.height as i16
+                                && z >= 1
+                                && z <= config.depth as i16
+                        }) {
+                            lines.push(
+                                line.iter()
+                                    .map(|&(x, y, z)| (config.column_at(x as u8, z as u8), y as u8))
+                                    .collect(),
+                            );
+                        }
+                    }
+                }
+            }
+        }
+        lines
+    }
+
+    fn check_every_line(config: Config, expected_lines: usize) {
+        let lines = all_lines(config);
+        assert!(lines.len() == expected_lines);
+
+        for line in lines.iter() {
+            for token in [Token::YELLOW, Token::RED].iter() {
+                // Tokens are set directly, so that the line does not need support below
+                let mut board = Board::with_config(config);
+                let mut position = 0;
+                for &(x, y) in line.iter() {
+                    position |= board.cell_bit(x, y);
+                }
+                board.positions[token.index()] = position;
+
+                for &(x, y) in line.iter() {
+                    let win = board
+                        .winner_at_cell(x, y)
+                        .unwrap_or_else(|| panic!("{:?} not found from {:?}", line, (x, y)));
+                    assert!(win.token == *token);
+                    assert!(win.lines == vec![line.clone()], "{:?}", line);
+                    if board.column_height(x) == y {
+                        assert!(board.have_winner_at_column(x), "{:?}", line);
+                    }
+                }
+
+                // Removing any token breaks the line
+                for &(x, y) in line.iter() {
+                    let mut broken = board.clone();
+                    broken.positions[token.index()] &= !board.cell_bit(x, y);
+                    for &(x, y) in line.iter() {
+                        assert!(broken.winner_at_cell(x, y).is_none(), "{:?}", line);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_every_winning_line() {
+        check_every_line(Config::default(), 69);
+        check_every_line(Config::new(9, 7, 5).expect("invalid config"), 92);
+        check_every_line(Config::new(4, 4, 3).expect("invalid config"), 24);
+    }
+
+    #[test]
+    fn test_is_full() {
+        let mut board = Board::new();
+        assert!(!board.is_full());
+        for column in 1..=board.width() {
+            for _ in 1..=board.height() {
+                assert!(!board.is_full());
+                board
+                    .add_token(column, &Token::YELLOW)
+                    .expect("could not add token");
+            }
+        }
+        assert!(board.is_full());
+        assert!(board.add_token(1, &Token::RED) == Ok(false));
+
+        let mut small_board = Board::with_config(Config::new(6, 5, 4).expect("invalid config"));
+        for column in 1..=6 {
+            for _ in 1..=5 {
+                assert!(!small_board.is_full());
+                small_board
+                    .add_token(column, &Token::RED)
+                    .expect("could not add token");
+            }
+        }
+        assert!(small_board.is_full());
+        assert!(board.add_token(board.width() + 1, &Token::RED).is_err());
+    }
+
+    #[test]
+    fn test_serializing() {
+        let mut board = Board::new();
+        let serialized = board.to_number();
+        assert!(serialized == Ok(0));
+        assert!(Board::from_number(0, board.config()).to_number() == serialized);
+        for column in 1..=board.width() {
+            for row in 1..=board.height() {
+                if (row + column) % 2 == 0 {
+                    board
+                        .add_token(column, &Token::YELLOW)
+                        .expect("could not add token");
+                } else {
+                    board
+                        .add_token(column, &Token::RED)
+                        .expect("could not add token");
+                }
+            }
+        }
+        let board_full = Board::from_number(
+            board.to_number().expect("could not serialize"),
+            board.config(),
+        );
+        assert!(board_full.is_full());
+        assert!(board_full.to_number() == board.to_number());
+        assert!(board_full.to_number().expect("could not serialize") > 1);
+    }
+
+    #[test]
+    fn test_validated_import() {
+        let config = Config::default();
+        let mut board = Board::new();
+        play(
+            &mut board,
+            &[
+                (4, Token::YELLOW),
+                (4, Token::RED),
+                (3, Token::YELLOW),
+                (5, Token::RED),
+            ],
+        );
+        let imported =
+            Board::try_from_number(board.to_number().expect("could not serialize"), config)
+                .expect("valid position rejected");
+        assert!(imported.to_number() == board.to_number());
+        let mut imported =
+            Board::from_string(&board.to_string(), config).expect("valid position rejected");
+        assert!(imported.to_string() == board.to_string());
+        assert!(imported.add_token(4, &Token::YELLOW).is_ok());
+
+        assert!(
+            Board::try_from_number(3u128.pow(42), config).err() == Some(PositionError::Overflow)
+        );
+        assert!(Board::try_from_number(3u128.pow(42) - 1, config).is_err());
+        // A yellow token on the second row of the first column
+        assert!(
+            Board::try_from_number(3u128.pow(7) + 2 * 3, config).err()
+                == Some(PositionError::FloatingToken { column: 1, row: 2 })
+        );
+        assert!(
+            Board::try_from_number(1 + 3, config).err()
+                == Some(PositionError::TokenCountImbalance { most: 2, fewest: 0 })
+        );
+        // A red token alone, as if red had moved first
+        assert!(Board::try_from_number(2, config).err() == Some(PositionError::OutOfTurn));
+    }
+
+    #[test]
+    fn test_from_string_errors() {
+        let config = Config::new(4, 4, 3).expect("invalid config");
+        assert!(Board::from_string("________________", config).is_ok());
+        assert!(
+            Board::from_string("_______", config).err()
+                == Some(PositionError::WrongLength {
+                    expected: 16,
+                    found: 7
+                })
+        );
+        assert!(
+            Board::from_string("YRX_____________", config).err()
+                == Some(PositionError::InvalidCharacter('X'))
+        );
+        assert!(
+            Board::from_string("YR____Y_________", config).err()
+                == Some(PositionError::FloatingToken { column: 3, row: 2 })
+        );
+        assert!(
+            Board::from_string("RY__RY__RY______", config).err()
+                == Some(PositionError::BothPlayersWin)
+        );
+        assert!(Board::from_string("RYY_RY__R_______", config).is_ok());
+        assert!(
+            Board::from_string("RY__R___R___R___", config).err()
+                == Some(PositionError::TokenCountImbalance { most: 4, fewest: 1 })
+        );
+        assert!(
+            Board::from_string("_R______________", config).err() == Some(PositionError::OutOfTurn)
+        );
+        assert!(
+            PositionError::FloatingToken { column: 3, row: 2 }.to_string()
+                == "the token at column 3, row 2 has nothing below it"
+        );
+    }
+
+    #[test]
+    fn test_to_string() {
+        let mut board = Board::new();
+        assert!(board.to_string() == "__________________________________________");
+        board
+            .add_token(1, &Token::YELLOW)
+            .expect("Could not add token");
+        assert!(board.to_string() == "Y_________________________________________");
+        board
+            .add_token(1, &Token::RED)
+            .expect("Could not add token");
+        assert!(board.to_string() == "Y______R__________________________________");
+        board
+            .add_token(2, &Token::RED)
+            .expect("Could not add token");
+        assert!(board.to_string() == "YR_____R__________________________________");
+
+        // The cells are in the same order as in to_number
+        for x in 1..=board.width() {
+            for y in 1..=board.height() {
+                let mut cell = Board::new();
+                cell.set_cell(x, y, Some(Token::RED));
+                let index = (y - 1) as usize * board.width() as usize + (x - 1) as usize;
+                assert!(cell.to_number() == Ok(2 * 3u128.pow(index as u32)));
+                assert!(cell.to_string().find('R') == Some(index));
+            }
+        }
+    }
+
+    #[test]
+    fn test_moves_notation() {
+        let config = Config::default();
+        let board = Board::from_moves("44455554221", config).expect("could not parse");
+        assert!(
+            board.moves()
+                == [
+                    Action::DROP(4),
+                    Action::DROP(4),
+                    Action::DROP(4),
+                    Action::DROP(5),
+                    Action::DROP(5),
+                    Action::DROP(5),
+                    Action::DROP(5),
+                    Action::DROP(4),
+                    Action::DROP(2),
+                    Action::DROP(2),
+                    Action::DROP(1),
+                ]
+        );
+        assert!(board.to_moves() == "44455554221");
+        assert!(board.get_color_at_cell(4, 4) == Some(&Token::RED));
+        assert!(board.get_color_at_cell(1, 1) == Some(&Token::YELLOW));
+        assert!(
+            board.to_fen(&Token::RED) == "_______/_______/___RY__/___YR__/_R_RY__/YY_YR__ R 11 4 2"
+        );
+        assert!(Board::from_moves("", config).is_ok());
+
+        let mut board = Board::new();
+        play(&mut board, &[(7, Token::YELLOW), (1, Token::RED)]);
+        assert!(board.to_moves() == "71");
+
+        let wide = Config::new(12, 6, 4).expect("invalid config");
+        let board = Board::from_moves("ac1", wide).expect("could not parse");
+        assert!(board.moves() == [Action::DROP(10), Action::DROP(12), Action::DROP(1)]);
+        assert!(board.to_moves() == "ac1");
+
+        // The other moves come back from their prefixes
+        let board = Board::from_moves("4455-4", config).expect("could not parse");
+        assert!(board.moves().last() == Some(&Action::POP(4)));
+        assert!(board.get_color_at_cell(4, 1) == Some(&Token::RED));
+        assert!(board.to_moves() == "4455-4");
+        let copy = Board::from_moves(&board.to_moves(), config).expect("could not parse");
+        assert!(copy.to_string() == board.to_string());
+        assert!(copy.moves() == board.moves());
+    }
+
+    #[test]
+    fn test_moves_notation_errors() {
+        let config = Config::default();
+        assert!(
+            Board::from_moves("448", config).err()
+                == Some(PositionError::IllegalMove {
+                    index: 3,
+                    reason: "out of bounds"
+                })
+        );
+        assert!(
+            Board::from_moves("40", config).err()
+                == Some(PositionError::IllegalMove {
+                    index: 2,
+                    reason: "out of bounds"
+                })
+        );
+        assert!(
+            Board::from_moves("4444444", config).err()
+                == Some(PositionError::IllegalMove {
+                    index: 7,
+                    reason: "the column is full"
+                })
+        );
+        assert!(Board::from_moves("1212121", config).is_ok());
+        assert!(
+            Board::from_moves("12121213", config).err()
+                == Some(PositionError::IllegalMove {
+                    index: 8,
+                    reason: "the game is already over"
+                })
+        );
+        assert!(
+            Board::from_moves("4 4", config).err() == Some(PositionError::InvalidCharacter(' '))
+        );
+        assert!(
+            Board::from_moves("44-", config).err()
+                == Some(PositionError::InvalidFormat("missing column"))
+        );
+        assert!(
+            Board::from_moves("4#4", config).err()
+                == Some(PositionError::IllegalMove {
+                    index: 2,
+                    reason: "the move cannot be made there"
+                })
+        );
+    }
+
+    #[test]
+    fn test_mirror() {
+        let config = Config::default();
+        let board = Board::from_moves("1123", config).expect("could not parse");
+        let mirrored = board.mirror();
+        assert!(mirrored.to_moves() == "7765");
+        assert!(mirrored.to_number() == Board::from_moves("7765", config).unwrap().to_number());
+        assert!(mirrored.mirror().to_number() == board.to_number());
+        assert!(mirrored.get_color_at_cell(7, 2) == Some(&Token::RED));
+
+        assert!(board.to_number() != mirrored.to_number());
+        assert!(board.canonical_key() == mirrored.canonical_key());
+        let smallest = board
+            .to_number()
+            .and_then(|number| Ok(number.min(mirrored.to_number()?)));
+        assert!(board.canonical_key() == smallest);
+
+        let different = Board::from_moves("1132", config).expect("could not parse");
+        assert!(different.canonical_key() != board.canonical_key());
+
+        // A symmetric position is its own mirror
+        let symmetric = Board::from_moves("43355", config).expect("could not parse");
+        assert!(symmetric.canonical_key() == symmetric.to_number());
+
+        // Even widths have no middle column
+        let even = Config::new(6, 5, 4).expect("invalid config");
+        let board = Board::from_moves("3412", even).expect("could not parse");
+        assert!(board.mirror().to_moves() == "4365");
+        assert!(board.canonical_key() == board.mirror().canonical_key());
+    }
+
+    #[test]
+    fn test_zobrist_hash() {
+        let config = Config::default();
+        let empty = Board::new();
+        assert!(empty.zobrist_hash() == 0);
+
+        // The same position reached through different move orders
+        let board = Board::from_moves("4453", config).expect("could not parse");
+        let transposed = Board::from_moves("5344", config).expect("could not parse");
+        assert!(board.to_number() == transposed.to_number());
+        assert!(board.zobrist_hash() == transposed.zobrist_hash());
+        assert!(board.zobrist_hash() == board.compute_hash());
+
+        // Same cells with the colors swapped
+        let swapped = Board::from_moves("4435", config).expect("could not parse");
+        assert!(swapped.zobrist_hash() != board.zobrist_hash());
+
+        // Positions loaded from other formats agree with the incremental hash
+        assert!(
+            Board::from_number(board.to_number().expect("could not serialize"), config)
+                .zobrist_hash()
+                == board.zobrist_hash()
+        );
+        let (from_fen, _) =
+            Board::from_fen(&board.to_fen(&Token::YELLOW)).expect("could not parse");
+        assert!(from_fen.zobrist_hash() == board.zobrist_hash());
+        assert!(board.mirror().zobrist_hash() == board.mirror().compute_hash());
+
+        let mut board = board;
+        let hash = board.zobrist_hash();
+        board
+            .add_token(1, &Token::YELLOW)
+            .expect("could not add token");
+        assert!(board.zobrist_hash() != hash);
+        board.undo_move();
+        assert!(board.zobrist_hash() == hash);
+        while board.undo_move().is_some() {}
+        assert!(board.zobrist_hash() == 0);
+        // Redoing everything also plays again the move taken back first
+        while board.redo_move().is_some() {}
+        assert!(board.zobrist_hash() == board.compute_hash());
+        board.undo_move();
+        assert!(board.zobrist_hash() == hash);
+        board.reset();
+        assert!(board.zobrist_hash() == 0);
+    }
+
+    // Columns of the drops the board allows, central ones first
+    fn center_first(board: &Board) -> Vec<u8> {
+        let mut moves: Vec<Action> = board.legal_moves().into_iter().map(Action::DROP).collect();
+        board.sort_center_first(&mut moves);
+        moves.iter().map(|action| action.column()).collect()
+    }
+
+    #[test]
+    fn test_legal_moves() {
+        let mut board = Board::new();
+        assert!(board.legal_moves() == [1, 2, 3, 4, 5, 6, 7]);
+        assert!(center_first(&board) == [4, 3, 5, 2, 6, 1, 7]);
+        assert!(!board.is_legal(0));
+        assert!(!board.is_legal(8));
+
+        for row in 1..=board.height() {
+            assert!(board.is_legal(3));
+            board
+                .add_token(3, &Token::YELLOW)
+                .expect("could not add token");
+            assert!(board.column_height(3) == row);
+        }
+        assert!(!board.is_legal(3));
+        assert!(board.column_height(8) == 0);
+        assert!(board.legal_moves() == [1, 2, 4, 5, 6, 7]);
+        assert!(center_first(&board) == [4, 5, 2, 6, 1, 7]);
+
+        let even = Board::with_config(Config::new(6, 5, 4).expect("invalid config"));
+        assert!(center_first(&even) == [3, 4, 2, 5, 1, 6]);
+
+        // Yellow would complete the second row in columns 3 and 7, so red must not play there,
+        // and once there is a token in column 3 yellow wins there unless red blocks
+        let board = Board::from_moves("64455161", Config::default()).expect("could not parse");
+        let forcing_first = |board: &Board, token: &Token, next: &Token| {
+            let mut moves: Vec<Action> =
+                center_first(board).into_iter().map(Action::DROP).collect();
+            board.sort_forcing_first(&mut moves, token, next);
+            moves
+                .iter()
+                .map(|action| action.column())
+                .collect::<Vec<u8>>()
+        };
+        assert!(forcing_first(&board, &Token::RED, &Token::YELLOW) == [4, 5, 2, 6, 1, 3, 7]);
+        assert!(forcing_first(&board, &Token::YELLOW, &Token::RED) == [4, 3, 5, 2, 6, 1, 7]);
+        let board = Board::from_moves("644551613", Config::default()).expect("could not parse");
+        assert!(forcing_first(&board, &Token::YELLOW, &Token::RED) == [3, 4, 5, 2, 6, 1, 7]);
+        assert!(forcing_first(&board, &Token::RED, &Token::YELLOW) == [3, 4, 5, 2, 6, 1, 7]);
+    }
+
+    #[test]
+    fn test_threats() {
+        let mut board = Board::new();
+        assert!(board.threats(&Token::YELLOW).is_empty());
+        assert!(board.winning_moves(&Token::YELLOW).is_empty());
+
+        // Yellow has 2, 3 and 4 on the bottom row: both ends win right away
+        play(
+            &mut board,
+            &[(2, Token::YELLOW), (3, Token::YELLOW), (4, Token::YELLOW)],
+        );
+        assert!(board.winning_moves(&Token::YELLOW) == [1, 5]);
+        assert!(board.winning_moves(&Token::RED).is_empty());
+        assert!(
+            board.threats(&Token::YELLOW)
+                == vec![
+                    Threat {
+                        column: 1,
+                        row: 1,
+                        parity: Parity::ODD
+                    },
+                    Threat {
+                        column: 5,
+                        row: 1,
+                        parity: Parity::ODD
+                    }
+                ]
+        );
+    }
+
+    #[test]
+    fn test_unsafe_columns() {
+        let config = Config::default();
+        // Red has 1, 2 and 3 on the second row, and (4, 1) is empty: a yellow token in column 4
+        // would let red win on top of it
+        let board = Board::from_moves("11227373", config).expect("could not parse");
+        assert!(board.get_color_at_cell(3, 2) == Some(&Token::RED));
+        assert!(board.unsafe_columns(&Token::YELLOW) == [4]);
+        assert!(board.unsafe_columns(&Token::RED).is_empty());
+        assert!(board.winning_moves(&Token::RED).is_empty());
+        assert!(
+            board.threats(&Token::RED)
+                == vec![Threat {
+                    column: 4,
+                    row: 2,
+                    parity: Parity::EVEN
+                }]
+        );
+    }
+
+    #[test]
+    fn test_threats_at_the_edges() {
+        // Lines cannot continue past the top of a column or the sides of the board
+        let mut board = Board::new();
+        for &(x, y) in [(1, 4), (1, 5), (1, 6), (2, 1), (5, 1), (6, 1), (7, 1)].iter() {
+            board.set_cell(x, y, Some(Token::YELLOW));
+        }
+        for y in 1..=3 {
+            board.set_cell(1, y, Some(Token::RED));
+        }
+        assert!(
+            board.threats(&Token::YELLOW)
+                == vec![Threat {
+                    column: 4,
+                    row: 1,
+                    parity: Parity::ODD
+                }]
+        );
+        assert!(board.threats(&Token::RED).is_empty());
+    }
+
+    #[test]
+    fn test_threats_match_brute_force() {
+        let config = Config::default();
+        for moves in ["4453542", "3344553", "12345671234567", "444455553"].iter() {
+            let board = Board::from_moves(moves, config).expect("could not parse");
+            for token in [Token::YELLOW, Token::RED].iter() {
+                let threats = board.threats(token);
+                for x in 1..=board.width() {
+                    for y in 1..=board.height() {
+                        if board.get_color_at_cell(x, y).is_some() {
+                            continue;
+                        }
+                        let mut filled = board.clone();
+                        filled.set_cell(x, y, Some(token.clone()));
+                        let expected = filled.winner_at_cell(x, y).is_some();
+                        let found = threats.iter().any(|t| t.column == x && t.row == y);
+                        assert!(expected == found, "{} {:?} {:?}", moves, token, (x, y));
+                    }
+                }
+
+                let mut expected_moves = Vec::new();
+                for column in board.legal_moves() {
+                    let mut played = board.clone();
+                    played
+                        .add_token(column, token)
+                        .expect("could not add token");
+                    if played.have_winner_at_column(column) {
+                        expected_moves.push(column);
+                    }
+                }
+                assert!(board.winning_moves(token) == expected_moves);
+            }
+        }
+    }
+
+    #[test]
+    fn test_open_windows() {
+        let wrapped = Config::default().with_wrap(true).expect("invalid config");
+        let blocked = Config::default()
+            .with_blockers(true)
+            .expect("invalid config");
+        let positions = [
+            ("4453542", Config::default()),
+            ("12345671234567", Config::default()),
+            ("7711226", wrapped),
+            ("16611216", Config::score_four()),
+            ("4453542", blocked),
+        ];
+        for (moves, config) in positions.iter() {
+            let mut board = Board::from_moves(moves, *config).expect("could not parse");
+            if config.blockers {
+                board.add_blocker(3, 2).expect("could not add blocker");
+            }
+            for token in [Token::YELLOW, Token::RED].iter() {
+                let mut expected = vec![0; config.connect as usize + 1];
+                for line in all_lines(*config) {
+                    let cells: Vec<Option<&Token>> = line
+                        .iter()
+                        .map(|&(x, y)| match board.is_blocker(x, y) {
+                            true => Some(&Token::GREEN),
+                            false => board.get_color_at_cell(x, y),
+                        })
+                        .collect();
+                    if cells
+                        .iter()
+                        .all(|cell| cell.is_none_or(|cell| cell == token))
+                    {
+                        expected[cells.iter().filter(|cell| cell.is_some()).count()] += 1;
+                    }
+                }
+                assert!(
+                    board.open_windows(token) == expected,
+                    "{} {:?}",
+                    moves,
+                    token
+                );
+            }
+        }
+        let board = Board::new();
+        assert!(board.open_windows(&Token::YELLOW) == vec![69, 0, 0, 0, 0]);
+        assert!(board.center_closeness(&Token::YELLOW) == 0);
+        assert!(board.empty_cells() == 42);
+        let board = Board::from_moves("4434", Config::default()).expect("could not parse");
+        assert!(board.center_closeness(&Token::YELLOW) == 6 + 4);
+        assert!(board.center_closeness(&Token::RED) == 2 * 6);
+        assert!(board.empty_cells() == 38);
+    }
+
+    #[test]
+    fn test_fen() {
+        let mut board = Board::new();
+        assert!(
+            board.to_fen(&Token::YELLOW)
+                == "_______/_______/_______/_______/_______/_______ Y 0 4 2"
+        );
+        play(
+            &mut board,
+            &[
+                (4, Token::YELLOW),
+                (4, Token::RED),
+                (3, Token::YELLOW),
+                (5, Token::RED),
+                (4, Token::YELLOW),
+            ],
+        );
+        let fen = board.to_fen(&Token::RED);
+        assert!(fen == "_______/_______/_______/___Y___/___R___/__YYR__ R 5 4 2");
+
+        let (parsed, side_to_move) = Board::from_fen(&fen).expect("could not parse");
+        assert!(side_to_move == Token::RED);
+        assert!(parsed.to_number() == board.to_number());
+        assert!(parsed.to_fen(&side_to_move) == fen);
+
+        let (small, side_to_move) =
+            Board::from_fen("___/_R_/YY_ R 3 3 2").expect("could not parse");
+        assert!(side_to_move == Token::RED);
+        assert!(small.width() == 3 && small.height() == 3);
+        assert!(small.config().connect == 3);
+        assert!(small.get_color_at_cell(2, 2) == Some(&Token::RED));
+        assert!(small.to_string() == "YY__R____");
+
+        // The length of the winning line and the number of players come back without any help,
+        // even before anyone has played
+        let config = Config::new(5, 4, 3)
+            .expect("invalid config")
+            .with_players(3)
+            .expect("invalid config");
+        let empty = Board::with_config(config);
+        let fen = empty.to_fen(&Token::YELLOW);
+        assert!(fen == "_____/_____/_____/_____ Y 0 3 3");
+        let (parsed, side_to_move) = Board::from_fen(&fen).expect("could not parse");
+        assert!(side_to_move == Token::YELLOW);
+        assert!(parsed.config == config);
+        assert!(parsed.to_fen(&side_to_move) == fen);
+    }
+
+    #[test]
+    fn test_fen_errors() {
+        let error = |fen: &str| Board::from_fen(fen).err();
+        assert!(error("____/____/____/____ Y 0 4 2").is_none());
+        assert!(matches!(
+            error("____/____/____/____ Y"),
+            Some(PositionError::InvalidFormat(_))
+        ));
+        assert!(matches!(
+            error("____/____/____/____ Y 0"),
+            Some(PositionError::InvalidFormat(_))
+        ));
+        assert!(matches!(
+            error("____/____/____/____ Y 0 four 2"),
+            Some(PositionError::InvalidFormat(_))
+        ));
+        assert!(matches!(
+            error("____/____/____/____ Y 0 4 2 wrap wrap"),
+            Some(PositionError::InvalidFormat(_))
+        ));
+        assert!(matches!(
+            error("____/____/____/____ Y 0 7 2"),
+            Some(PositionError::InvalidSize(_))
+        ));
+        assert!(matches!(
+            error("____/____/____/____ Y 0 4 1"),
+            Some(PositionError::InvalidSize(_))
+        ));
+        // Green does not play in a two-player game, and blockers need the field
+        assert!(error("____/____/____/YRG_ Y 3 4 2") == Some(PositionError::InvalidCharacter('G')));
+        assert!(error("____/____/____/X___ Y 0 4 2") == Some(PositionError::InvalidCharacter('X')));
+        assert!(matches!(
+            error("____/___/____/____ Y 0 4 2"),
+            Some(PositionError::InvalidFormat(_))
+        ));
+        assert!(matches!(
+            error("____/____/____/____ X 0 4 2"),
+            Some(PositionError::InvalidFormat(_))
+        ));
+        assert!(matches!(
+            error("____/____/____/____ Y zero 4 2"),
+            Some(PositionError::InvalidFormat(_))
+        ));
+        assert!(matches!(
+            error("/ Y 0 4 2"),
+            Some(PositionError::InvalidSize(_))
+        ));
+        assert!(error("____/____/____/Y___ Y 1 4 2") == Some(PositionError::WrongSideToMove));
+        assert!(
+            error("____/____/____/Y___ R 3 4 2")
+                == Some(PositionError::MoveCountMismatch {
+                    expected: 1,
+                    found: 3
+                })
+        );
+        assert!(
+            error("____/____/Y___/____ R 1 4 2")
+                == Some(PositionError::FloatingToken { column: 1, row: 2 })
+        );
+        assert!(error("____/____/____/Y?__ R 1 4 2") == Some(PositionError::InvalidCharacter('?')));
+    }
+
+    #[test]
+    fn test_pop_token() {
+        let mut board = Board::new();
+        play(
+            &mut board,
+            &[(4, Token::YELLOW), (4, Token::RED), (4, Token::YELLOW)],
+        );
+        let before = board.zobrist_hash();
+        assert!(!board.can_pop(4, &Token::RED));
+        assert!(board.pop_token(4, &Token::RED) == Ok(false));
+        assert!(board.pop_token(3, &Token::YELLOW) == Ok(false));
+        assert!(board.pop_token(8, &Token::YELLOW).is_err());
+
+        assert!(board.pop_token(4, &Token::YELLOW) == Ok(true));
+        assert!(board.get_color_at_cell(4, 1) == Some(&Token::RED));
+        assert!(board.get_color_at_cell(4, 2) == Some(&Token::YELLOW));
+        assert!(board.column_height(4) == 2);
+        assert!(board.zobrist_hash() == board.compute_hash());
+        assert!(board.to_moves() == "444-4");
+        assert!(board.mirror().to_moves() == "444-4");
+
+        assert!(board.undo_move() == Some(Action::POP(4)));
+        assert!(board.zobrist_hash() == before);
+        assert!(board.get_color_at_cell(4, 1) == Some(&Token::YELLOW));
+        assert!(board.redo_move() == Some(Action::POP(4)));
+        assert!(board.last_move() == Some((Action::POP(4), Token::YELLOW)));
+    }
+
+    #[test]
+    fn test_power_discs() {
+        let config = Config::default()
+            .with_blockers(true)
+            .expect("invalid config");
+        let mut board = Board::with_config(config);
+        play(
+            &mut board,
+            &[(1, Token::YELLOW), (1, Token::RED), (1, Token::YELLOW)],
+        );
+        let before = board.zobrist_hash();
+
+        // The anvil knocks the whole column off and lands at the bottom
+        assert!(board.play(Action::ANVIL(1), &Token::RED) == Ok(true));
+        assert!(board.column_height(1) == 1);
+        assert!(board.get_color_at_cell(1, 1) == Some(&Token::RED));
+        assert!(board.zobrist_hash() == board.compute_hash());
+        assert!(board.undo_move() == Some(Action::ANVIL(1)));
+        assert!(board.zobrist_hash() == before);
+        assert!(board.to_string().starts_with("Y______R______Y"));
+        assert!(board.redo_move() == Some(Action::ANVIL(1)));
+        assert!(board.column_height(1) == 1);
+
+        // The bomb only takes out the token of an opponent
+        assert!(!board.can_play(Action::BOMB(1), &Token::RED));
+        assert!(!board.can_play(Action::BOMB(2), &Token::YELLOW));
+        assert!(board.play(Action::BOMB(1), &Token::YELLOW) == Ok(true));
+        assert!(board.column_height(1) == 0);
+        assert!(board.undo_move() == Some(Action::BOMB(1)));
+        assert!(board.get_color_at_cell(1, 1) == Some(&Token::RED));
+
+        // The wall is a blocker, which anvils and bombs cannot move
+        assert!(board.play(Action::WALL(1), &Token::YELLOW) == Ok(true));
+        assert!(board.is_blocker(1, 2));
+        assert!(!board.can_play(Action::ANVIL(1), &Token::RED));
+        assert!(!board.can_play(Action::BOMB(1), &Token::YELLOW));
+        assert!(board.undo_move() == Some(Action::WALL(1)));
+        assert!(!board.is_blocker(1, 2));
+        assert!(!Board::new().can_play(Action::WALL(1), &Token::YELLOW));
+
+        assert!(board.play(Action::EXTRA(2), &Token::YELLOW) == Ok(true));
+        assert!(board.get_color_at_cell(2, 1) == Some(&Token::YELLOW));
+        assert!(board.play(Action::ANVIL(8), &Token::YELLOW).is_err());
+        assert!(board.to_moves() == "111!1+2");
+        assert!(board.mirror().to_moves() == "777!7+6");
+        assert!(board.moves_of(&Token::RED) == [Action::DROP(1), Action::ANVIL(1)]);
+        assert!(board.zobrist_hash() == board.compute_hash());
+    }
+
+    #[test]
+    fn test_winner_anywhere() {
+        let mut board = Board::new();
+        assert!(board.winner(&Token::YELLOW).is_none());
+        play(
+            &mut board,
+            &[
+                (1, Token::YELLOW),
+                (2, Token::YELLOW),
+                (3, Token::YELLOW),
+                (4, Token::YELLOW),
+                (7, Token::RED),
+                (7, Token::RED),
+                (7, Token::RED),
+                (7, Token::RED),
+                (7, Token::RED),
+            ],
+        );
+        let yellow = board.winner(&Token::YELLOW).expect("yellow has a line");
+        assert!(yellow.lines == vec![vec![(1, 1), (2, 1), (3, 1), (4, 1)]]);
+        let red = board.winner(&Token::RED).expect("red has a line");
+        assert!(red.lines == vec![vec![(7, 1), (7, 2), (7, 3), (7, 4), (7, 5)]]);
+    }
+
+    #[test]
+    fn test_wrap() {
+        assert!(Config::new(3, 6, 4)
+            .expect("invalid config")
+            .with_wrap(true)
+            .is_err());
+        let cylinder = Config::default().with_wrap(true).expect("invalid config");
+        check_every_line(cylinder, 105);
+        check_every_line(
+            Config::new(6, 5, 5)
+                .expect("invalid config")
+                .with_wrap(true)
+                .expect("invalid config"),
+            48,
+        );
+
+        let mut board = Board::with_config(cylinder);
+        play(
+            &mut board,
+            &[(6, Token::RED), (7, Token::RED), (1, Token::RED)],
+        );
+        assert!(board.winner_at_column(1).is_none());
+        assert!(board.winning_moves(&Token::RED) == [2, 5]);
+        assert!(board.add_token(2, &Token::RED) == Ok(true));
+        let win = board.winner_at_column(2).expect("the line goes around");
+        assert!(win.lines == vec![vec![(6, 1), (7, 1), (1, 1), (2, 1)]]);
+
+        let fen = "_______/_______/_______/_______/_______/RY___YR Y 4 4 2 wrap";
+        let (parsed, _) = Board::from_fen(fen).expect("could not parse");
+        assert!(parsed.config == cylinder);
+        assert!(parsed.to_fen(&Token::YELLOW) == fen);
+        assert!(matches!(
+            Board::from_fen("_______/RR___RR Y 4 4 2 flat"),
+            Err(PositionError::InvalidFormat(_))
+        ));
+
+        // The same tokens on a flat board do not make a line
+        let mut flat = Board::new();
+        play(
+            &mut flat,
+            &[
+                (6, Token::RED),
+                (7, Token::RED),
+                (1, Token::RED),
+                (2, Token::RED),
+            ],
+        );
+        assert!(flat.winner_at_column(2).is_none());
+    }
+
+    #[test]
+    fn test_wrap_full_row() {
+        // A row as long as the line has no ends, it is reported once
+        let config = Config::new(4, 4, 4)
+            .expect("invalid config")
+            .with_wrap(true)
+            .expect("invalid config");
+        let mut board = Board::with_config(config);
+        play(
+            &mut board,
+            &[(3, Token::YELLOW), (4, Token::YELLOW), (1, Token::YELLOW)],
+        );
+        assert!(board.winning_moves(&Token::YELLOW) == [2]);
+        assert!(board.add_token(2, &Token::YELLOW) == Ok(true));
+        let win = board.winner_at_column(2).expect("the row is complete");
+        assert!(win.lines == vec![vec![(3, 1), (4, 1), (1, 1), (2, 1)]]);
+        let win = board.winner(&Token::YELLOW).expect("the row is complete");
+        assert!(win.lines.len() == 1 && win.lines[0].len() == 4);
+    }
+
+    #[test]
+    fn test_players() {
+        assert!(Config::default().with_players(1).is_err());
+        assert!(Config::default().with_players(5).is_err());
+        // Each cell takes a base 5 digit with four players, 9x7 is too large to be written as a
+        // number but can still be played
+        let large = Config::new(9, 7, 4)
+            .expect("invalid config")
+            .with_players(4)
+            .expect("invalid config");
+        let board = Board::from_moves("12345", large).expect("could not parse");
+        assert!(board.to_number() == Err(PositionError::TooManyCells));
+        assert!(board.canonical_key() == Err(PositionError::TooManyCells));
+        assert!(Board::try_from_number(0, large).err() == Some(PositionError::TooManyCells));
+        assert!(board.to_fen(&Token::RED).ends_with(" R 5 4 4"));
+
+        let config = Config::new(8, 6, 4)
+            .expect("invalid config")
+            .with_players(4)
+            .expect("invalid config");
+        let board = Board::from_moves("12344", config).expect("could not parse");
+        assert!(board.get_color_at_cell(1, 1) == Some(&Token::YELLOW));
+        assert!(board.get_color_at_cell(2, 1) == Some(&Token::RED));
+        assert!(board.get_color_at_cell(3, 1) == Some(&Token::GREEN));
+        assert!(board.get_color_at_cell(4, 1) == Some(&Token::BLUE));
+        assert!(board.get_color_at_cell(4, 2) == Some(&Token::YELLOW));
+        assert!(board.to_string().starts_with("YRGB____"));
+        assert!(board.next_player(&Token::BLUE) == Token::YELLOW);
+        assert!(Token::YELLOW.previous(4) == Token::BLUE);
+        assert!(board.zobrist_hash() == board.compute_hash());
+
+        let copy = Board::from_number(board.to_number().expect("could not serialize"), config);
+        assert!(copy.to_string() == board.to_string());
+        assert!(
+            Board::try_from_number(board.to_number().expect("could not serialize"), config).is_ok()
+        );
+        assert!(board.mirror().mirror().to_string() == board.to_string());
+
+        let fen = board.to_fen(&Token::RED);
+        assert!(fen.ends_with(" R 5 4 4"));
+        let (parsed, side) = Board::from_fen(&fen).expect("could not parse");
+        assert!(parsed.players() == 4 && side == Token::RED);
+        assert!(parsed.to_string() == board.to_string());
+        // Blue cannot have played before green, nor green before red
+        assert!(
+            Board::from_fen("____/____/____/YRB_ Y 3 4 4").err() == Some(PositionError::OutOfTurn)
+        );
+        assert!(
+            Board::from_fen("____/____/____/Y_G_ R 2 4 3").err() == Some(PositionError::OutOfTurn)
+        );
+        // Everyone has played this round, so yellow moves next
+        assert!(
+            Board::from_fen("____/____/____/YRG_ R 3 4 3").err()
+                == Some(PositionError::WrongSideToMove)
+        );
+        assert!(Board::from_fen("____/____/____/YRG_ Y 3 4 3").is_ok());
+        let (three, side) =
+            Board::from_fen("____/____/____/YR__ G 2 4 3").expect("could not parse");
+        assert!(three.players() == 3 && side == Token::GREEN);
+    }
+
+    #[test]
+    fn test_score_four() {
+        let config = Config::score_four();
+        assert!(config.columns() == 16);
+        assert!(Config::score_four().with_wrap(true).is_err());
+        assert!(Config::default().with_depth(3).is_err());
+        assert!(Config::default().with_depth(0).is_err());
+        // The 76 lines of Score Four: 48 along the axes, 24 diagonals on a plane and 4 through
+        // the middle of the cube. The 3x3x3 cube has 49.
+        check_every_line(config, 76);
+        check_every_line(
+            Config::new(3, 3, 3)
+                .and_then(|config| config.with_depth(3))
+                .expect("invalid config"),
+            49,
+        );
+
+        // A diagonal going up across the rows, from the front left column to the back right one
+        let mut board = Board::with_config(config);
+        for (i, &column) in [1, 6, 11, 16].iter().enumerate() {
+            for _ in 0..i {
+                board
+                    .add_token(column, &Token::RED)
+                    .expect("could not add token");
+            }
+            board
+                .add_token(column, &Token::YELLOW)
+                .expect("could not add token");
+        }
+        let win = board.winner_at_column(16).expect("no winner");
+        assert!(win.lines == vec![vec![(1, 1), (6, 2), (11, 3), (16, 4)]]);
+        assert!(board.legal_moves().len() == 15);
+        assert!(center_first(&board)[..4] == [6, 7, 10, 11]);
+        assert!(board.mirror().get_color_at_cell(4, 1) == Some(&Token::YELLOW));
+        assert!(board.mirror().get_color_at_cell(13, 4) == Some(&Token::YELLOW));
+        assert!(
+            Board::from_number(board.to_number().expect("could not serialize"), config).to_string()
+                == board.to_string()
+        );
+        assert!(board.zobrist_hash() == board.compute_hash());
+
+        assert!(board.to_fen(&Token::YELLOW).starts_with(
+            "____|____|____|___Y/____|____|__Y_|___R/____|_Y__|__R_|___R/Y___|_R__|__R_|___R "
+        ));
+        let fen =
+            "____|____|____|____/____|____|____|____/____|____|____|____/Y___|_R__|____|___Y R 3 4 2";
+        let (parsed, _) = Board::from_fen(fen).expect("could not parse");
+        assert!(parsed.config == config);
+        assert!(parsed.get_color_at_cell(6, 1) == Some(&Token::RED));
+        assert!(parsed.get_color_at_cell(16, 1) == Some(&Token::YELLOW));
+        assert!(parsed.to_fen(&Token::RED) == fen);
+        assert!(matches!(
+            Board::from_fen("____|___/____|____ Y 0 4 2"),
+            Err(PositionError::InvalidFormat(_))
+        ));
+    }
+
+    #[test]
+    fn test_blockers() {
+        assert!(Board::new().add_blocker(1, 1).is_err());
+        let config = Config::default()
+            .with_blockers(true)
+            .expect("invalid config");
+        let mut board = Board::with_config(config);
+        assert!(board.add_blocker(4, 1) == Ok(true));
+        assert!(board.add_blocker(4, 1) == Ok(false));
+        assert!(board.add_blocker(8, 1).is_err());
+        // A blocker in the air leaves the cells below it out of the game
+        assert!(board.add_blocker(7, 5) == Ok(true));
+        assert!(board.is_blocker(4, 1) && board.get_color_at_cell(4, 1).is_none());
+        assert!(board.column_height(7) == 5);
+
+        // Blockers break lines and tokens land on top of them
+        for &column in [1, 2, 3, 4, 5].iter() {
+            board
+                .add_token(column, &Token::YELLOW)
+                .expect("could not add token");
+        }
+        assert!(board.get_color_at_cell(4, 2) == Some(&Token::YELLOW));
+        assert!(board.winner(&Token::YELLOW).is_none());
+        assert!(!board.can_pop(4, &Token::YELLOW));
+        assert!(board.pop_token(4, &Token::YELLOW) == Ok(false));
+        assert!(board.can_pop(5, &Token::YELLOW));
+        assert!(board.zobrist_hash() == board.compute_hash());
+
+        // Yellow is ahead by five tokens, so the position is only checked for serialization
+        let copy = Board::from_number(board.to_number().expect("could not serialize"), config);
+        assert!(copy.to_string() == board.to_string());
+        assert!(board.to_string().starts_with("YYYXY__"));
+        assert!(board.mirror().mirror().to_string() == board.to_string());
+
+        board.reset();
+        assert!(board.is_blocker(4, 1) && board.is_blocker(7, 5));
+        assert!(board.zobrist_hash() == board.compute_hash());
+        for _ in 0..board.height() {
+            for column in 1..=board.width() {
+                let _ = board.add_token(column, &Token::RED);
+            }
+        }
+        assert!(board.is_full());
+
+        let (parsed, side) =
+            Board::from_fen("____X_/X_____/_XR_Y_ Y 2 4 2 blockers").expect("could not parse");
+        assert!(side == Token::YELLOW && parsed.config().blockers);
+        assert!(parsed.to_fen(&side) == "____X_/X_____/_XR_Y_ Y 2 4 2 blockers");
+        assert!(
+            Board::from_string("_X_R__Y__", Config::new(3, 3, 3).expect("invalid config")).is_err()
+        );
+
+        let mut random = Board::with_config(config);
+        random
+            .add_random_blockers(10)
+            .expect("could not add blockers");
+        assert!(
+            (1..=random.width())
+                .map(|x| random.column_height(x))
+                .sum::<u8>()
+                == 10
+        );
+        assert!(random.validate().is_ok());
+
+        // A new game drops as many blockers again, wherever they fall
+        random
+            .add_token(1, &Token::YELLOW)
+            .expect("could not add token");
+        random.reset();
+        assert!(random.blockers.count_ones() == 10);
+        assert!(random.positions[Token::YELLOW.index()] == 0);
+        assert!(random.zobrist_hash() == random.compute_hash());
     }
 }
@@ -0,0 +1,448 @@
+use crate::board::{Action, Board, Config, PositionError, Token, Win, MAX_PLAYERS, TOKENS};
+use crate::rules::{Ruleset, RulesetObject};
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum GameOutcome {
+    InProgress,
+    // The cells of every line completed by the winning move
+    Win { token: Token, line: Vec<(u8, u8)> },
+    // The player completed the line and lost, in misère games
+    Loss { token: Token, line: Vec<(u8, u8)> },
+    Draw,
+}
+
+impl GameOutcome {
+    // Merges the winning lines into the list of their cells, without duplicates
+    pub fn from_win(win: Win) -> GameOutcome {
+        let mut line: Vec<(u8, u8)> = Vec::new();
+        for cell in win.lines.concat() {
+            if !line.contains(&cell) {
+                line.push(cell);
+            }
+        }
+        GameOutcome::Win {
+            token: win.token,
+            line,
+        }
+    }
+
+    // Whether the game ended with a win for the player. When a player loses in a misère game,
+    // all the others win.
+    pub fn is_winner(&self, player: &Token) -> bool {
+        match self {
+            GameOutcome::Win { token, .. } => token == player,
+            GameOutcome::Loss { token, .. } => token != player,
+            GameOutcome::InProgress | GameOutcome::Draw => false,
+        }
+    }
+}
+
+// A match: turn order and the score across games, with the moves and the end of the game left
+// to the ruleset. It does not know anything about how the game is displayed or who the players are.
+pub struct Game {
+    board: Board,
+    rules: RulesetObject,
+    current_player: Token,
+    outcome: GameOutcome,
+    // Games won by each player, indexed by Token::index
+    scores: [u8; MAX_PLAYERS as usize],
+}
+
+impl Game {
+    #[allow(dead_code)]
+    pub fn new(config: Config, rules: RulesetObject) -> Game {
+        Game::from_board(Board::with_config(config), rules)
+    }
+
+    // Starts on a board set up beforehand, like one with blockers on it, and no tokens
+    pub fn from_board(board: Board, rules: RulesetObject) -> Game {
+        Game {
+            board,
+            rules,
+            current_player: Token::YELLOW,
+            outcome: GameOutcome::InProgress,
+            scores: [0; MAX_PLAYERS as usize],
+        }
+    }
+
+    // Starts from the position reached by a sequence of moves, in the notation of
+    // Board::to_moves, each of them checked against the rules
+    #[allow(dead_code)]
+    pub fn from_moves(
+        value: &str,
+        config: Config,
+        rules: RulesetObject,
+    ) -> Result<Game, PositionError> {
+        let mut game = Game::new(config, rules);
+        game.play_moves(value)?;
+        Result::Ok(game)
+    }
+
+    // Plays a sequence of moves in the notation of Board::to_moves, stopping at the first one
+    // that is not legal
+    pub fn play_moves(&mut self, value: &str) -> Result<(), PositionError> {
+        for (i, action) in Action::parse_moves(value)?.into_iter().enumerate() {
+            if let Err(reason) = self.play(action) {
+                return Result::Err(PositionError::IllegalMove {
+                    index: i + 1,
+                    reason,
+                });
+            }
+        }
+        Result::Ok(())
+    }
+
+    #[allow(dead_code)]
+    pub fn to_moves(&self) -> String {
+        self.board.to_moves()
+    }
+
+    pub fn board(&self) -> &Board {
+        &self.board
+    }
+
+    pub fn rules(&self) -> &dyn Ruleset {
+        self.rules.as_ref()
+    }
+
+    pub fn current_player(&self) -> &Token {
+        &self.current_player
+    }
+
+    pub fn outcome(&self) -> &GameOutcome {
+        &self.outcome
+    }
+
+    #[allow(dead_code)]
+    pub fn moves(&self) -> Vec<Action> {
+        self.board.moves()
+    }
+
+    pub fn score(&self, token: &Token) -> u8 {
+        self.scores[token.index()]
+    }
+
+    // Players taking part in the game, in turn order
+    pub fn players(&self) -> &[Token] {
+        &TOKENS[..self.board.players() as usize]
+    }
+
+    // Whoever won the game gets a point, or loses it when the winning move is taken back
+    fn update_scores(&mut self, won: bool) {
+        for token in TOKENS[..self.board.players() as usize].iter() {
+            if self.outcome.is_winner(token) {
+                let score = &mut self.scores[token.index()];
+                *score = if won { *score + 1 } else { *score - 1 };
+            }
+        }
+    }
+
+    // Checks the result of the move just played, then passes the turn.
+    // The turn passes even if the game is over, so the next player starts the next one.
+    fn finish_move(&mut self) {
+        self.outcome = self.rules.outcome(&self.board);
+        self.update_scores(true);
+        self.current_player = self.rules.next_player(&self.board, &self.current_player);
+    }
+
+    // Plays the move for the current player
+    pub fn play(&mut self, action: Action) -> Result<&GameOutcome, &'static str> {
+        if self.outcome != GameOutcome::InProgress {
+            return Result::Err("the game is over");
+        }
+        self.rules
+            .apply_move(&mut self.board, action, &self.current_player)?;
+        self.finish_move();
+        Result::Ok(&self.outcome)
+    }
+
+    // Takes back the last move, also taking back the point if it had won the game
+    pub fn undo_move(&mut self) -> Option<Action> {
+        let (_, player) = self.board.last_move()?;
+        let action = self.rules.undo_move(&mut self.board)?;
+        self.update_scores(false);
+        self.outcome = GameOutcome::InProgress;
+        self.current_player = player;
+        Some(action)
+    }
+
+    pub fn redo_move(&mut self) -> Option<Action> {
+        let action = self.board.redo_move()?;
+        self.finish_move();
+        Some(action)
+    }
+
+    // Starts a new game, keeping the scores
+    pub fn reset(&mut self) {
+        self.board.reset();
+        self.outcome = GameOutcome::InProgress;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::rules::{ClassicRules, MisereRules, PopOutRules, PowerUpRules};
+
+    fn classic() -> RulesetObject {
+        Box::new(ClassicRules {})
+    }
+
+    fn popout() -> RulesetObject {
+        Box::new(PopOutRules {})
+    }
+
+    #[test]
+    fn test_turns() {
+        let mut game = Game::new(Config::default(), classic());
+        assert!(*game.current_player() == Token::YELLOW);
+        assert!(game.play(Action::DROP(4)) == Ok(&GameOutcome::InProgress));
+        assert!(*game.current_player() == Token::RED);
+        assert!(game.play(Action::DROP(4)) == Ok(&GameOutcome::InProgress));
+        assert!(*game.current_player() == Token::YELLOW);
+        assert!(game.board().get_color_at_cell(4, 1) == Some(&Token::YELLOW));
+        assert!(game.board().get_color_at_cell(4, 2) == Some(&Token::RED));
+        assert!(game.moves() == [Action::DROP(4), Action::DROP(4)]);
+
+        assert!(game.play(Action::DROP(8)).is_err());
+        for _ in 0..4 {
+            game.play(Action::DROP(4)).expect("could not play");
+        }
+        assert!(game.play(Action::DROP(4)).is_err());
+        assert!(*game.current_player() == Token::YELLOW);
+    }
+
+    #[test]
+    fn test_win() {
+        let mut game = Game::new(Config::default(), classic());
+        for column in [1, 1, 2, 2, 3, 3].iter() {
+            game.play(Action::DROP(*column)).expect("could not play");
+        }
+        assert!(
+            game.play(Action::DROP(4))
+                == Ok(&GameOutcome::Win {
+                    token: Token::YELLOW,
+                    line: vec![(1, 1), (2, 1), (3, 1), (4, 1)],
+                })
+        );
+        assert!(game.score(&Token::YELLOW) == 1);
+        assert!(game.score(&Token::RED) == 0);
+        assert!(game.play(Action::DROP(5)).is_err());
+
+        // Taking back the winning move also takes back the point
+        assert!(game.undo_move() == Some(Action::DROP(4)));
+        assert!(*game.outcome() == GameOutcome::InProgress);
+        assert!(*game.current_player() == Token::YELLOW);
+        assert!(game.score(&Token::YELLOW) == 0);
+        assert!(game.redo_move() == Some(Action::DROP(4)));
+        assert!(game.score(&Token::YELLOW) == 1);
+
+        // The loser starts the next game
+        game.reset();
+        assert!(*game.outcome() == GameOutcome::InProgress);
+        assert!(game.moves().is_empty());
+        assert!(*game.current_player() == Token::RED);
+        assert!(game.score(&Token::YELLOW) == 1);
+    }
+
+    #[test]
+    fn test_from_moves() {
+        let game = Game::from_moves("4455", Config::default(), classic()).expect("could not parse");
+        assert!(*game.current_player() == Token::YELLOW);
+        assert!(*game.outcome() == GameOutcome::InProgress);
+        assert!(game.to_moves() == "4455");
+
+        let mut game =
+            Game::from_moves("1212121", Config::default(), classic()).expect("could not parse");
+        assert!(matches!(
+            game.outcome(),
+            GameOutcome::Win {
+                token: Token::YELLOW,
+                ..
+            }
+        ));
+        assert!(game.score(&Token::YELLOW) == 1);
+        assert!(game.undo_move() == Some(Action::DROP(1)));
+        assert!(game.to_moves() == "121212");
+
+        assert!(Game::from_moves("9", Config::default(), classic()).is_err());
+    }
+
+    #[test]
+    fn test_draw() {
+        // A connect 3 board only two rows high can only be won horizontally
+        let mut game = Game::new(Config::new(3, 2, 3).expect("invalid config"), classic());
+        for column in [1, 2, 3, 1, 2].iter() {
+            assert!(game.play(Action::DROP(*column)) == Ok(&GameOutcome::InProgress));
+        }
+        assert!(game.play(Action::DROP(3)) == Ok(&GameOutcome::Draw));
+        assert!(game.score(&Token::YELLOW) == 0);
+        assert!(game.score(&Token::RED) == 0);
+    }
+
+    #[test]
+    fn test_popout() {
+        let mut game =
+            Game::from_moves("4455", Config::default(), popout()).expect("could not parse");
+        assert!(game.play(Action::POP(3)).is_err());
+        game.play(Action::POP(4)).expect("could not pop");
+        assert!(game.board().get_color_at_cell(4, 1) == Some(&Token::RED));
+        assert!(game.board().get_color_at_cell(4, 2).is_none());
+        assert!(game.to_moves() == "4455-4");
+        assert!(*game.current_player() == Token::RED);
+
+        assert!(game.undo_move() == Some(Action::POP(4)));
+        assert!(game.board().get_color_at_cell(4, 1) == Some(&Token::YELLOW));
+        assert!(game.board().get_color_at_cell(4, 2) == Some(&Token::RED));
+        assert!(game.redo_move() == Some(Action::POP(4)));
+        assert!(game.to_moves() == "4455-4");
+
+        assert!(Game::from_moves("44-4", Config::default(), classic()).is_err());
+        assert!(
+            Game::from_moves("44-", Config::default(), popout()).err()
+                == Some(PositionError::InvalidFormat("missing column"))
+        );
+    }
+
+    #[test]
+    fn test_powerup() {
+        let config = Config::default()
+            .with_blockers(true)
+            .expect("invalid config");
+        let mut game =
+            Game::from_moves("34+5", config, Box::new(PowerUpRules {})).expect("could not parse");
+        // Yellow played the extra turn disc and moves again
+        assert!(*game.current_player() == Token::YELLOW);
+        game.play(Action::BOMB(4)).expect("could not play");
+        assert!(*game.current_player() == Token::RED);
+        assert!(game.board().get_color_at_cell(4, 1).is_none());
+        assert!(game.to_moves() == "34+5*4");
+
+        assert!(game.undo_move() == Some(Action::BOMB(4)));
+        assert!(*game.current_player() == Token::YELLOW);
+        assert!(game.undo_move() == Some(Action::EXTRA(5)));
+        assert!(*game.current_player() == Token::YELLOW);
+        assert!(game.redo_move() == Some(Action::EXTRA(5)));
+        assert!(*game.current_player() == Token::YELLOW);
+        assert!(game.play(Action::EXTRA(6)).is_err());
+    }
+
+    #[test]
+    fn test_powerup_reset() {
+        let config = Config::default()
+            .with_blockers(true)
+            .expect("invalid config");
+        let mut board = Board::with_config(config);
+        board.add_blocker(7, 1).expect("could not add blocker");
+        let mut game = Game::from_board(board, Box::new(PowerUpRules {}));
+        game.play_moves("#4").expect("could not play");
+        assert!(game.board().is_blocker(4, 1));
+
+        // The wall goes with the game, the blocker set up before it stays
+        game.reset();
+        assert!(!game.board().is_blocker(4, 1));
+        assert!(game.board().is_blocker(7, 1));
+        assert!(game
+            .rules()
+            .discs(game.board(), &Token::YELLOW)
+            .contains(&"wall"));
+        let mut expected = Board::with_config(config);
+        expected.add_blocker(7, 1).expect("could not add blocker");
+        assert!(game.board().zobrist_hash() == expected.zobrist_hash());
+    }
+
+    #[test]
+    fn test_popout_win() {
+        // Yellow pops its token under the red one in column 1: red lines up on the bottom row and
+        // yellow on the second one. The player who pops wins.
+        let mut game =
+            Game::from_moves("1223344117", Config::default(), popout()).expect("could not parse");
+        assert!(*game.outcome() == GameOutcome::InProgress);
+        assert!(
+            game.play(Action::POP(1))
+                == Ok(&GameOutcome::Win {
+                    token: Token::YELLOW,
+                    line: vec![(1, 2), (2, 2), (3, 2), (4, 2)],
+                })
+        );
+        assert!(game.score(&Token::YELLOW) == 1);
+        assert!(game.score(&Token::RED) == 0);
+    }
+
+    #[test]
+    fn test_misere() {
+        let mut game = Game::from_moves("112233", Config::default(), Box::new(MisereRules {}))
+            .expect("could not parse");
+        game.play(Action::DROP(4)).expect("could not play");
+        assert!(matches!(
+            game.outcome(),
+            GameOutcome::Loss {
+                token: Token::YELLOW,
+                ..
+            }
+        ));
+        assert!(game.score(&Token::YELLOW) == 0);
+        assert!(game.score(&Token::RED) == 1);
+
+        game.undo_move();
+        assert!(game.score(&Token::RED) == 0);
+    }
+
+    #[test]
+    fn test_three_players() {
+        let config = Config::new(9, 7, 4)
+            .expect("invalid config")
+            .with_players(3)
+            .expect("invalid config");
+        let mut game = Game::new(config, classic());
+        assert!(game.players() == [Token::YELLOW, Token::RED, Token::GREEN]);
+        for column in [1, 2, 3].iter() {
+            game.play(Action::DROP(*column)).expect("could not play");
+        }
+        assert!(*game.current_player() == Token::YELLOW);
+        assert!(game.undo_move() == Some(Action::DROP(3)));
+        assert!(*game.current_player() == Token::GREEN);
+        game.play(Action::DROP(3)).expect("could not play");
+
+        // Yellow completes its column in the fourth round
+        for _ in 0..2 {
+            for column in [1, 2, 3].iter() {
+                game.play(Action::DROP(*column)).expect("could not play");
+            }
+        }
+        assert!(matches!(
+            game.play(Action::DROP(1)),
+            Ok(GameOutcome::Win {
+                token: Token::YELLOW,
+                ..
+            })
+        ));
+        assert!(game.score(&Token::YELLOW) == 1);
+        assert!(game.score(&Token::GREEN) == 0);
+
+        // The next player starts the next game
+        game.reset();
+        assert!(*game.current_player() == Token::RED);
+    }
+
+    #[test]
+    fn test_misere_three_players() {
+        let config = Config::new(9, 7, 4)
+            .expect("invalid config")
+            .with_players(3)
+            .expect("invalid config");
+        let mut game = Game::from_moves("123123123", config, Box::new(MisereRules {}))
+            .expect("could not parse");
+        game.play(Action::DROP(1)).expect("could not play");
+        assert!(matches!(
+            game.outcome(),
+            GameOutcome::Loss {
+                token: Token::YELLOW,
+                ..
+            }
+        ));
+        assert!(game.score(&Token::YELLOW) == 0);
+        assert!(game.score(&Token::RED) == 1);
+        assert!(game.score(&Token::GREEN) == 1);
+    }
+}
@@ -1,3 +1,4 @@
+use crate::game::Game;
 use crate::interface::Move;
 use crate::{board, interface};
 use pancurses::Window;
@@ -10,7 +11,7 @@ impl interface::GameInterface for HumanController {
         String::from("Human")
     }
 
-    fn play(&self, _: &board::Board, _: u8, _: board::Token, window: &Window) -> VecDeque<Move> {
+    fn play(&self, _: &Game, _: u8, _: board::Token, window: &Window) -> VecDeque<Move> {
         let mut moves = VecDeque::new();
 
         loop {
@@ -31,10 +32,46 @@ impl interface::GameInterface for HumanController {
                     moves.push_back(Move::RIGHT);
                     break;
                 }
+                Some(pancurses::Input::KeyUp) => {
+                    moves.push_back(Move::UP);
+                    break;
+                }
+                Some(pancurses::Input::KeyDown) => {
+                    moves.push_back(Move::DOWN);
+                    break;
+                }
                 Some(pancurses::Input::Character(' ')) => {
                     moves.push_back(Move::DROP);
                     break;
                 }
+                Some(pancurses::Input::Character('p')) => {
+                    moves.push_back(Move::POP);
+                    break;
+                }
+                Some(pancurses::Input::Character('a')) => {
+                    moves.push_back(Move::ANVIL);
+                    break;
+                }
+                Some(pancurses::Input::Character('w')) => {
+                    moves.push_back(Move::WALL);
+                    break;
+                }
+                Some(pancurses::Input::Character('b')) => {
+                    moves.push_back(Move::BOMB);
+                    break;
+                }
+                Some(pancurses::Input::Character('e')) => {
+                    moves.push_back(Move::EXTRA);
+                    break;
+                }
+                Some(pancurses::Input::Character('u')) => {
+                    moves.push_back(Move::UNDO);
+                    break;
+                }
+                Some(pancurses::Input::Character('r')) => {
+                    moves.push_back(Move::REDO);
+                    break;
+                }
                 _ => (),
             }
         }
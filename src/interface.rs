@@ -1,11 +1,24 @@
 use super::board;
+use crate::board::{Action, Config};
+use crate::game::Game;
 use pancurses::Window;
 use std::collections::VecDeque;
 
 pub enum Move {
     LEFT,
     RIGHT,
+    // Towards the back and the front of a three-dimensional board
+    UP,
+    DOWN,
     DROP,
+    POP,
+    // Playing one of the special discs of Power Up
+    ANVIL,
+    WALL,
+    BOMB,
+    EXTRA,
+    UNDO,
+    REDO,
 }
 
 pub trait GameInterface {
@@ -13,7 +26,7 @@ pub trait GameInterface {
 
     fn play(
         &self,
-        board: &board::Board,
+        game: &Game,
         cursor_position: u8,
         player_color: board::Token,
         window: &Window,
@@ -21,3 +34,38 @@ pub trait GameInterface {
 }
 
 pub type InterfaceObject = Box<dyn GameInterface>;
+
+// Moves taking the cursor from its column to the one of the action, then playing it
+pub fn moves_to_play(config: &Config, cursor_position: u8, action: Action) -> VecDeque<Move> {
+    let mut moves = VecDeque::new();
+    let (cursor_x, cursor_z) = config.column_place(cursor_position);
+    let (target_x, target_z) = config.column_place(action.column());
+
+    if target_x > cursor_x {
+        for _ in cursor_x..target_x {
+            moves.push_back(Move::RIGHT);
+        }
+    } else {
+        for _ in target_x..cursor_x {
+            moves.push_back(Move::LEFT)
+        }
+    }
+    if target_z > cursor_z {
+        for _ in cursor_z..target_z {
+            moves.push_back(Move::UP);
+        }
+    } else {
+        for _ in target_z..cursor_z {
+            moves.push_back(Move::DOWN)
+        }
+    }
+    moves.push_back(match action {
+        Action::DROP(_) => Move::DROP,
+        Action::POP(_) => Move::POP,
+        Action::ANVIL(_) => Move::ANVIL,
+        Action::WALL(_) => Move::WALL,
+        Action::BOMB(_) => Move::BOMB,
+        Action::EXTRA(_) => Move::EXTRA,
+    });
+    moves
+}
@@ -1,24 +1,73 @@
+#![allow(clippy::upper_case_acronyms)]
+
 mod board;
+mod game;
 mod human_controller;
 mod interface;
 mod minimax_bot;
 mod random_bot;
+mod rules;
 mod ui;
+#[macro_use]
 extern crate clap;
 
+use crate::board::{Board, Config};
+use crate::game::{Game, GameOutcome};
 use crate::human_controller::HumanController;
 use crate::interface::InterfaceObject;
 use crate::minimax_bot::MinimaxBot;
 use crate::random_bot::RandomBot;
+use crate::rules::{ClassicRules, MisereRules, PopOutRules, PowerUpRules, RulesetObject};
 
 fn choose_player_type(param: Option<&str>) -> InterfaceObject {
     match param {
         Some("random") => Box::new(RandomBot {}) as InterfaceObject,
-        Some("minimax") => Box::new(MinimaxBot {}) as InterfaceObject,
+        Some("minimax") => Box::new(MinimaxBot::new()) as InterfaceObject,
         _ => Box::new(HumanController {}) as InterfaceObject,
     }
 }
 
+fn choose_ruleset(param: Option<&str>) -> RulesetObject {
+    match param {
+        Some("popout") => Box::new(PopOutRules {}) as RulesetObject,
+        Some("misere") => Box::new(MisereRules {}) as RulesetObject,
+        Some("powerup") => Box::new(PowerUpRules {}) as RulesetObject,
+        _ => Box::new(ClassicRules {}) as RulesetObject,
+    }
+}
+
+fn validate_dimension(value: String) -> Result<(), String> {
+    match value.parse::<u8>() {
+        Ok(size) if size > 0 => Ok(()),
+        _ => Err(String::from("must be a positive number")),
+    }
+}
+
+// Cells written as column:row and separated by commas, e.g. 4:1,2:3
+fn parse_cells(value: &str) -> Result<Vec<(u8, u8)>, String> {
+    value
+        .split(',')
+        .map(|cell| {
+            let mut parts = cell.split(':').map(|part| part.trim().parse::<u8>());
+            match (parts.next(), parts.next(), parts.next()) {
+                (Some(Ok(column)), Some(Ok(row)), None) => Ok((column, row)),
+                _ => Err(format!("invalid cell '{}', expected column:row", cell)),
+            }
+        })
+        .collect()
+}
+
+fn validate_cells(value: String) -> Result<(), String> {
+    parse_cells(&value).map(|_| ())
+}
+
+fn validate_count(value: String) -> Result<(), String> {
+    match value.parse::<u8>() {
+        Ok(_) => Ok(()),
+        _ => Err(String::from("must be a number")),
+    }
+}
+
 fn main() {
     let arg_matches = clap::App::new("Connect 4 Rust")
         .arg(
@@ -37,11 +86,166 @@ fn main() {
                 .default_value("human")
                 .possible_values(&["human", "random", "minimax"]),
         )
+        .arg(
+            clap::Arg::with_name("player3_type")
+                .long("player3")
+                .short("3")
+                .takes_value(true)
+                .default_value("human")
+                .possible_values(&["human", "random", "minimax"]),
+        )
+        .arg(
+            clap::Arg::with_name("player4_type")
+                .long("player4")
+                .short("4")
+                .takes_value(true)
+                .default_value("human")
+                .possible_values(&["human", "random", "minimax"]),
+        )
+        .arg(
+            clap::Arg::with_name("players")
+                .long("players")
+                .takes_value(true)
+                .default_value("2")
+                .possible_values(&["2", "3", "4"]),
+        )
+        .arg(
+            clap::Arg::with_name("width")
+                .long("width")
+                .takes_value(true)
+                .default_value("7")
+                .validator(validate_dimension),
+        )
+        .arg(
+            clap::Arg::with_name("height")
+                .long("height")
+                .takes_value(true)
+                .default_value("6")
+                .validator(validate_dimension),
+        )
+        .arg(
+            clap::Arg::with_name("depth")
+                .long("depth")
+                .takes_value(true)
+                .default_value("1")
+                .validator(validate_dimension)
+                .help("Rows of columns one behind the other, more than 1 makes the board three-dimensional"),
+        )
+        .arg(
+            clap::Arg::with_name("connect")
+                .long("connect")
+                .takes_value(true)
+                .default_value("4")
+                .possible_values(&["3", "4", "5", "6"]),
+        )
+        .arg(
+            clap::Arg::with_name("wrap")
+                .long("wrap")
+                .help("Joins the left and right edges of the board, so lines can go around it"),
+        )
+        .arg(
+            clap::Arg::with_name("score_four")
+                .long("score-four")
+                .conflicts_with_all(&["width", "height", "depth", "connect", "wrap"])
+                .help("Plays Score Four, on a 4x4 grid of columns 4 tokens high"),
+        )
+        .arg(
+            clap::Arg::with_name("blockers")
+                .long("blockers")
+                .takes_value(true)
+                .validator(validate_cells)
+                .help("Puts neutral blockers in these cells, e.g. 4:1,2:3 for column 4, row 1 and column 2, row 3"),
+        )
+        .arg(
+            clap::Arg::with_name("random_blockers")
+                .long("random-blockers")
+                .takes_value(true)
+                .validator(validate_count)
+                .help("Drops this many neutral blockers in random columns"),
+        )
+        .arg(
+            clap::Arg::with_name("variant")
+                .long("variant")
+                .takes_value(true)
+                .default_value("classic")
+                .possible_values(&["classic", "popout", "misere", "powerup"]),
+        )
+        .arg(
+            clap::Arg::with_name("moves")
+                .long("moves")
+                .takes_value(true)
+                .help("Starts from the position reached by these columns, e.g. 4453, with '-' before a pop, '!', '#', '*' or '+' before a special disc"),
+        )
         .get_matches();
 
-    ui::UI::new(
-        choose_player_type(arg_matches.value_of("player1_type")),
-        choose_player_type(arg_matches.value_of("player2_type")),
-    )
-    .run();
+    let board_config = if arg_matches.is_present("score_four") {
+        Result::Ok(Config::score_four())
+    } else {
+        Config::new(
+            value_t_or_exit!(arg_matches, "width", u8),
+            value_t_or_exit!(arg_matches, "height", u8),
+            value_t_or_exit!(arg_matches, "connect", u8),
+        )
+        .and_then(|config| config.with_depth(value_t_or_exit!(arg_matches, "depth", u8)))
+    };
+    let config = match board_config
+        .and_then(|config| config.with_wrap(arg_matches.is_present("wrap")))
+        .and_then(|config| config.with_players(value_t_or_exit!(arg_matches, "players", u8)))
+        .and_then(|config| {
+            // Walls in Power Up are blockers too
+            config.with_blockers(
+                arg_matches.is_present("blockers")
+                    || arg_matches.is_present("random_blockers")
+                    || arg_matches.value_of("variant") == Some("powerup"),
+            )
+        }) {
+        Ok(config) => config,
+        Err(message) => {
+            clap::Error::with_description(message, clap::ErrorKind::InvalidValue).exit()
+        }
+    };
+
+    let mut board = Board::with_config(config);
+    let blockers = match arg_matches.value_of("blockers") {
+        Some(value) => parse_cells(value).unwrap_or_default(),
+        None => Vec::new(),
+    };
+    for (column, row) in blockers {
+        if let Err(message) = board.add_blocker(column, row) {
+            clap::Error::with_description(message, clap::ErrorKind::InvalidValue).exit()
+        }
+    }
+    if arg_matches.is_present("random_blockers") {
+        let count = value_t_or_exit!(arg_matches, "random_blockers", u8);
+        if let Err(message) = board.add_random_blockers(count) {
+            clap::Error::with_description(message, clap::ErrorKind::InvalidValue).exit()
+        }
+    }
+
+    let mut game = Game::from_board(board, choose_ruleset(arg_matches.value_of("variant")));
+    if let Some(moves) = arg_matches.value_of("moves") {
+        match game.play_moves(moves) {
+            Ok(()) if *game.outcome() == GameOutcome::InProgress => (),
+            Ok(()) => clap::Error::with_description(
+                "the moves already end the game",
+                clap::ErrorKind::InvalidValue,
+            )
+            .exit(),
+            Err(error) => {
+                clap::Error::with_description(&error.to_string(), clap::ErrorKind::InvalidValue)
+                    .exit()
+            }
+        }
+    }
+
+    let players = [
+        "player1_type",
+        "player2_type",
+        "player3_type",
+        "player4_type",
+    ][..config.players as usize]
+        .iter()
+        .map(|&name| choose_player_type(arg_matches.value_of(name)))
+        .collect();
+    ui::UI::new(game, players).run();
 }
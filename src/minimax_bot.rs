@@ -1,190 +1,742 @@
 use super::interface;
-use crate::board;
-use crate::board::{Board, Token, WIDTH};
+use crate::board::{Action, Board, Token, TOKENS};
+use crate::game::{Game, GameOutcome};
 use crate::interface::Move;
+use crate::rules::Ruleset;
 use pancurses::Window;
-use rand::Rng;
-use std::collections::VecDeque;
+use rand::seq::SliceRandom;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, VecDeque};
+use std::hash::{Hash, Hasher};
+use std::time::{Duration, Instant};
 
-pub struct MinimaxBot {}
+pub struct MinimaxBot {
+    weights: Weights,
+    // Time the bot takes for a move past MIN_DEPTH, by default as long as a search to MIN_DEPTH
+    // without pruning would take
+    move_time: Option<Duration>,
+}
+
+// Weights of the terms of the static evaluation, which scores the positions where the search
+// stops before the end of the game
+#[derive(Clone, Copy, Debug)]
+pub struct Weights {
+    // For each line of connect cells holding two tokens less than needed and nothing else, open
+    // twos when connecting four
+    pub open_two: i32,
+    // For each line holding one token less than needed and nothing else, open threes when
+    // connecting four
+    pub open_three: i32,
+    // For each token, times how many half columns its column is closer to the center than the
+    // edge of the board
+    pub center: i32,
+    // For each empty cell completing a line, on the rows the player gets to fill when the board
+    // fills up with everyone avoiding the cells their opponents need
+    pub good_threat: i32,
+    // For each empty cell completing a line, on the other rows
+    pub other_threat: i32,
+}
+
+impl Default for Weights {
+    fn default() -> Weights {
+        Weights {
+            open_two: 2,
+            open_three: 6,
+            center: 1,
+            good_threat: 16,
+            other_threat: 8,
+        }
+    }
+}
+
+impl Weights {
+    // Score of a position where the game goes on for the side of the bot, the other players
+    // counting together against it
+    fn evaluate(
+        &self,
+        rules: &dyn Ruleset,
+        board: &Board,
+        bot_color: &Token,
+        to_move: &Token,
+    ) -> i32 {
+        let connect = board.config().connect as usize;
+        let empty_cells = board.empty_cells();
+        let mut score = 0;
+        for token in TOKENS[..board.players() as usize].iter() {
+            let windows = board.open_windows(token);
+            let mut value = self.open_two * windows[connect - 2] as i32
+                + self.open_three * windows[connect - 1] as i32
+                + self.center * board.center_closeness(token) as i32;
+            // The player to move with an even number of empty cells gets the odd rows, like the
+            // first player on the classic board
+            let (odd, even) = board.threat_counts(token);
+            let (good, other) = if (token == to_move) != (empty_cells % 2 == 1) {
+                (odd, even)
+            } else {
+                (even, odd)
+            };
+            value += self.good_threat * good as i32 + self.other_threat * other as i32;
+            if token == bot_color {
+                score += value;
+            } else {
+                score -= value;
+            }
+        }
+        if rules.lines_win() {
+            score
+        } else {
+            -score
+        }
+    }
+}
 
 #[derive(PartialEq, Eq, PartialOrd, Ord, Clone)]
 struct BotMove {
     confidence: u8,
-    position: u8,
+    // Score of the move for the side of the bot
+    score: i32,
+    action: Action,
+    // Moves searched after the bot's one
+    depth: u8,
 }
 
-const MAX_DEPTH: u8 = 4;
+// Depth of the search in moves after the bot's one, always reached. The search then goes deeper
+// as long as the time for the move lasts.
+const MIN_DEPTH: u8 = 4;
+// Longest time for a move on the boards with many moves to choose from, where the search without
+// pruning took much longer
+const MAX_MOVE_TIME: Duration = Duration::from_secs(1);
 
-impl MinimaxBot {
-    fn max(
-        serialized_board: u128,
-        player_color: Token,
-        opponent_color: Token,
-        depth: u8,
-    ) -> BotMove {
-        let mut moves = VecDeque::new();
-
-        for x in 1..=WIDTH {
-            //print!("Depth:{}, adding column {}\n", depth, x);
-            let mut board = Board::from_number(serialized_board);
-            //print!("Board: {}\n", board.to_string());
-            if board
-                .add_token(x, &player_color)
-                .expect("Error adding token")
-            {
-                if board.have_winner_at_column(x) {
-                    // Victory, we immediately return
-                    return BotMove {
-                        position: x,
-                        confidence: 100,
-                    };
-                }
-                if board.is_full() {
-                    // Draw
-                    moves.push_back(BotMove {
-                        position: x,
-                        confidence: 50,
-                    });
-                    continue;
-                }
-                if depth < MAX_DEPTH {
-                    let recursive_move = MinimaxBot::min(
-                        board.to_number(),
-                        opponent_color.clone(),
-                        player_color.clone(),
-                        depth + 1,
-                    );
-                    if recursive_move.confidence == 100 {
-                        return BotMove {
-                            position: x,
-                            confidence: 100,
-                        };
-                    }
-                    moves.push_back(BotMove {
-                        position: x,
-                        confidence: recursive_move.confidence,
-                    });
-                } else {
-                    moves.push_back(BotMove {
-                        position: x,
-                        confidence: 20 + rand::thread_rng().gen_range(1, 20),
-                    });
-                }
+// Time the minimax search without pruning would take to MIN_DEPTH, visiting every position with
+// the given number of moves to choose from at each turn, when a position takes the given time
+fn move_time(position_time: Duration, moves: usize) -> Duration {
+    (1..=MIN_DEPTH as u32 + 1)
+        .try_fold(0u32, |positions, depth| {
+            (moves as u32)
+                .checked_pow(depth)
+                .and_then(|level| positions.checked_add(level))
+        })
+        .and_then(|positions| position_time.checked_mul(positions))
+        .map_or(MAX_MOVE_TIME, |time| time.min(MAX_MOVE_TIME))
+}
+
+// Score of a game won right away, for the side that won, above any evaluation of a game going
+// on. Every move it takes to win costs a point, so that quicker wins and slower losses score
+// better. A draw scores 0.
+const WIN_SCORE: i32 = 100_000;
+// Points of evaluation for each point of confidence of the bot in a game going on
+const CONFIDENCE_STEP: i32 = 10;
+
+// Score of a game won with the given number of moves from the position searched
+fn win_score(moves: u8) -> i32 {
+    WIN_SCORE - moves as i32
+}
+
+// Whether the score is for a game that ends within the search, no search going further than
+// u8::MAX moves
+fn is_decided(score: i32) -> bool {
+    score.abs() >= win_score(u8::MAX)
+}
+
+// Confidence of the bot in a move with the given score for its side: 100 for a win, 0 for a loss
+// and around 50 while the game goes on
+fn confidence(score: i32) -> u8 {
+    if is_decided(score) {
+        if score > 0 {
+            100
+        } else {
+            0
+        }
+    } else {
+        (50 + score / CONFIDENCE_STEP).clamp(1, 99) as u8
+    }
+}
+
+// What a score in the transposition table says about the real score of the position
+#[derive(Clone, Copy, PartialEq)]
+enum Bound {
+    EXACT,
+    // The search stopped early, the real score is at least this one
+    LOWER,
+    // No move reached the alpha-beta window, the real score is at most this one
+    UPPER,
+}
+
+// A position already searched, with the number of moves searched after the one to play, the
+// score and the best move found. Wins and losses are counted in moves from that position, not
+// from the one of the search, since the same position can be reached after more or fewer moves.
+#[derive(Clone, Copy)]
+struct TableEntry {
+    depth: u8,
+    score: i32,
+    bound: Bound,
+    action: Action,
+}
+
+// Score of a position for the transposition table, counting the moves to the end of a decided
+// game from the position rather than from the one of the search
+fn to_table(score: i32, ply: u8) -> i32 {
+    if is_decided(score) {
+        score + score.signum() * ply as i32
+    } else {
+        score
+    }
+}
+
+fn from_table(score: i32, ply: u8) -> i32 {
+    if is_decided(score) {
+        score - score.signum() * ply as i32
+    } else {
+        score
+    }
+}
+
+// Odd constant mixed into the key of a position in the transposition table for each player to
+// move, the golden ratio in 64 bits
+const PLAYER_KEY: u64 = 0x9E37_79B9_7F4A_7C15;
+
+// A negamax search with alpha-beta pruning. With more than two players it is paranoid: all the
+// other players are assumed to play together against the bot, so scores are always for either
+// the bot or the others, and only change sign when the turn goes from one side to the other.
+struct Search<'a> {
+    rules: &'a dyn Ruleset,
+    weights: &'a Weights,
+    bot_color: Token,
+    nodes: u64,
+    // The search gives up once this time has come
+    deadline: Option<Instant>,
+    // Moves searched after the first one, to count how many moves were played since the
+    // position of the search from the ones left to search
+    depth: u8,
+    // Positions already searched, kept from one depth to the next. They are found again when
+    // the same position is reached by moves in another order.
+    table: HashMap<u64, TableEntry>,
+}
+
+impl<'a> Search<'a> {
+    fn new(rules: &'a dyn Ruleset, weights: &'a Weights, bot_color: Token) -> Search<'a> {
+        Search {
+            rules,
+            weights,
+            bot_color,
+            nodes: 0,
+            deadline: None,
+            depth: 0,
+            table: HashMap::new(),
+        }
+    }
+
+    // Key of the position with the player to move in the transposition table. Besides the
+    // tokens, it depends on the special discs each player still has, which are only hashed in
+    // the variants that have some.
+    fn table_key(&self, board: &Board, player: &Token) -> u64 {
+        let mut key = board.zobrist_hash() ^ PLAYER_KEY.wrapping_mul(player.index() as u64 + 1);
+        for token in TOKENS[..board.players() as usize].iter() {
+            let discs = self.rules.discs(board, token);
+            if !discs.is_empty() {
+                let mut hasher = DefaultHasher::new();
+                (token.index(), discs).hash(&mut hasher);
+                key ^= hasher.finish();
             }
         }
-        match moves.iter().max() {
-            Some(m) => m.clone(),
-            None => BotMove {
-                position: 0,
-                confidence: 0,
-            },
+        key
+    }
+
+    fn same_side(&self, token: &Token, other: &Token) -> bool {
+        (*token == self.bot_color) == (*other == self.bot_color)
+    }
+
+    // Score of a game finished after the given number of moves for the side of the player, None
+    // while the game goes on
+    fn final_score(&self, outcome: &GameOutcome, player: &Token, moves: u8) -> Option<i32> {
+        let score = match outcome {
+            GameOutcome::InProgress => return None,
+            GameOutcome::Draw => 0,
+            _ if outcome.is_winner(&self.bot_color) => win_score(moves),
+            _ => -win_score(moves),
+        };
+        if self.same_side(player, &self.bot_color) {
+            Some(score)
+        } else {
+            Some(-score)
+        }
+    }
+
+    // Legal moves of the player, the given one first if there is one, then the central columns
+    fn sorted_moves(&self, board: &Board, player: &Token, first: Option<Action>) -> Vec<Action> {
+        let mut moves = self.rules.legal_moves(board, player);
+        board.sort_center_first(&mut moves);
+        board.sort_forcing_first(&mut moves, player, &board.next_player(player));
+        if let Some(first) = first {
+            if let Some(index) = moves.iter().position(|&action| action == first) {
+                moves.remove(index);
+                moves.insert(0, first);
+            }
         }
+        moves
     }
 
-    fn min(
-        serialized_board: u128,
-        player_color: Token,
-        opponent_color: Token,
+    // Moves played since the position of the search, when the given number are left to search
+    fn ply(&self, depth: u8) -> u8 {
+        self.depth - depth
+    }
+
+    // Score of the move for the side of the player who makes it, searching the given number of
+    // moves after it. None if the time for the move ran out.
+    fn score_move(
+        &mut self,
+        board: &mut Board,
+        action: Action,
+        player: &Token,
         depth: u8,
-    ) -> BotMove {
-        let mut moves = VecDeque::new();
-
-        for x in 1..=WIDTH {
-            let mut board = Board::from_number(serialized_board);
-            //print!("Min: Depth: {} Pos: {}\n", depth, x);
-            if board
-                .add_token(x, &player_color)
-                .expect("Error adding token")
-            {
-                if board.have_winner_at_column(x) {
-                    // Victory, we immediately return
-                    return BotMove {
-                        position: x,
-                        confidence: 0,
-                    };
-                }
-                if board.is_full() {
-                    // Draw
-                    moves.push_back(BotMove {
-                        position: x,
-                        confidence: 50,
-                    });
-                    continue;
+        alpha: i32,
+        beta: i32,
+    ) -> Option<i32> {
+        self.nodes += 1;
+        if self
+            .deadline
+            .is_some_and(|deadline| Instant::now() >= deadline)
+        {
+            return None;
+        }
+        self.rules
+            .apply_move(board, action, player)
+            .expect("Error adding token");
+        let moves = self.ply(depth) + 1;
+        let score = match self.final_score(&self.rules.outcome(board), player, moves) {
+            Some(score) => Some(score),
+            None => {
+                let next_player = self.rules.next_player(board, player);
+                if depth == 0 {
+                    let score =
+                        self.weights
+                            .evaluate(self.rules, board, &self.bot_color, &next_player);
+                    Some(if self.same_side(player, &self.bot_color) {
+                        score
+                    } else {
+                        -score
+                    })
+                } else if self.same_side(&next_player, player) {
+                    self.negamax(board, &next_player, depth - 1, alpha, beta)
+                } else {
+                    self.negamax(board, &next_player, depth - 1, -beta, -alpha)
+                        .map(|score| -score)
                 }
-                if depth < MAX_DEPTH {
-                    let recursive_move = MinimaxBot::max(
-                        board.to_number(),
-                        opponent_color.clone(),
-                        player_color.clone(),
-                        depth + 1,
-                    );
-                    if recursive_move.confidence == 0 {
-                        return BotMove {
-                            position: x,
-                            confidence: 0,
-                        };
-                    }
-                    moves.push_back(BotMove {
-                        position: x,
-                        confidence: recursive_move.confidence,
-                    });
+            }
+        };
+        self.rules.undo_move(board);
+        score
+    }
+
+    // Best score the player's side can reach, searching the given number of moves after the
+    // player's one. Scores outside of the alpha-beta window are only bounds.
+    fn negamax(
+        &mut self,
+        board: &mut Board,
+        player: &Token,
+        depth: u8,
+        mut alpha: i32,
+        mut beta: i32,
+    ) -> Option<i32> {
+        let ply = self.ply(depth);
+        let key = self.table_key(board, player);
+        let entry = self.table.get(&key).cloned();
+        if let Some(entry) = entry.filter(|entry| entry.depth >= depth) {
+            let score = from_table(entry.score, ply);
+            match entry.bound {
+                Bound::EXACT => return Some(score),
+                Bound::LOWER => alpha = alpha.max(score),
+                Bound::UPPER => beta = beta.min(score),
+            }
+            if alpha >= beta {
+                return Some(score);
+            }
+        }
+
+        let original_alpha = alpha;
+        let mut best: Option<(i32, Action)> = None;
+        for action in self.sorted_moves(board, player, entry.map(|entry| entry.action)) {
+            // After the first move, the others are only checked to be no better with a window
+            // of one point, and searched again if they are. The moves before the leaves are
+            // scored right away, so they get the whole window.
+            let score = if best.is_none() || depth == 0 {
+                self.score_move(board, action, player, depth, alpha, beta)?
+            } else {
+                let score = self.score_move(board, action, player, depth, alpha, alpha + 1)?;
+                if score > alpha && score < beta {
+                    self.score_move(board, action, player, depth, score, beta)?
                 } else {
-                    moves.push_back(BotMove {
-                        position: x,
-                        confidence: 20 + rand::thread_rng().gen_range(1, 20),
-                    });
+                    score
                 }
+            };
+            if best.is_none_or(|(best_score, _)| score > best_score) {
+                best = Some((score, action));
+            }
+            alpha = alpha.max(score);
+            if alpha >= beta {
+                break;
             }
         }
-        match moves.iter().min() {
-            Some(m) => m.clone(),
-            None => BotMove {
-                position: 0,
-                confidence: 0,
+
+        let (score, action) = match best {
+            Some(best) => best,
+            None => return Some(-win_score(ply)),
+        };
+        let bound = if score <= original_alpha {
+            Bound::UPPER
+        } else if score >= beta {
+            Bound::LOWER
+        } else {
+            Bound::EXACT
+        };
+        self.table.insert(
+            key,
+            TableEntry {
+                depth,
+                score: to_table(score, ply),
+                bound,
+                action,
             },
+        );
+        Some(score)
+    }
+
+    // Best move of the player, with the confidence of the bot in it, trying first the move that
+    // was best in the previous search. Moves with the same score are picked from at random.
+    fn best_move(
+        &mut self,
+        board: &mut Board,
+        player: &Token,
+        depth: u8,
+        first: Option<Action>,
+    ) -> Option<BotMove> {
+        self.depth = depth;
+        let moves = self.sorted_moves(board, player, first);
+        let mut best_score = None;
+        let mut best_moves = Vec::new();
+        let mut alpha = -WIN_SCORE - 1;
+        for action in moves {
+            let score = self.score_move(board, action, player, depth, alpha, WIN_SCORE + 1)?;
+            match best_score {
+                Some(best) if score < best => continue,
+                Some(best) if score == best => best_moves.push(action),
+                _ => {
+                    best_score = Some(score);
+                    best_moves = vec![action];
+                }
+            }
+            // Moves as good as the best one must get their exact score too, to tie with it
+            alpha = alpha.max(score - 1);
         }
+        let score = best_score?;
+        let action = *best_moves.choose(&mut rand::thread_rng())?;
+        let bot_score = if self.same_side(player, &self.bot_color) {
+            score
+        } else {
+            -score
+        };
+        Some(BotMove {
+            confidence: confidence(bot_score),
+            score: bot_score,
+            action,
+            depth,
+        })
     }
 }
 
-impl interface::GameInterface for MinimaxBot {
-    fn name(&self) -> String {
-        String::from("Bot")
+impl MinimaxBot {
+    pub fn new() -> MinimaxBot {
+        MinimaxBot::with_weights(Weights::default())
     }
 
-    fn play(
-        &self,
-        board: &board::Board,
-        cursor_position: u8,
-        color: Token,
-        _: &Window,
-    ) -> VecDeque<Move> {
-        let mut moves = VecDeque::new();
-        let opponent_color = match color {
-            Token::YELLOW => Token::RED,
-            Token::RED => Token::YELLOW,
-        };
+    pub fn with_weights(weights: Weights) -> MinimaxBot {
+        MinimaxBot {
+            weights,
+            move_time: None,
+        }
+    }
 
-        let target_position =
-            MinimaxBot::max(board.to_number(), color.clone(), opponent_color.clone(), 0).position;
+    #[allow(dead_code)]
+    pub fn with_move_time(self, move_time: Duration) -> MinimaxBot {
+        MinimaxBot {
+            move_time: Some(move_time),
+            ..self
+        }
+    }
 
-        if target_position > cursor_position {
-            for _ in cursor_position..target_position {
-                moves.push_back(Move::RIGHT);
+    // Searches one move deeper at a time, keeping the result of the deepest search that finished.
+    // The searches up to MIN_DEPTH always finish, the deeper ones only within the time for the
+    // move. Going from the shallowest search also means that the bot wins as soon as it can.
+    fn best_move(&self, rules: &dyn Ruleset, board: &mut Board, color: Token) -> BotMove {
+        let start = Instant::now();
+        let mut search = Search::new(rules, &self.weights, color.clone());
+        let mut best: Option<BotMove> = None;
+        for depth in 0..u8::MAX {
+            if depth > MIN_DEPTH && search.deadline.is_none() {
+                // The searches so far tell how long a position takes on this machine, about as
+                // long as it took the search without pruning
+                let move_time = self.move_time.unwrap_or_else(|| {
+                    let position_time = start.elapsed() / search.nodes.max(1) as u32;
+                    move_time(position_time, rules.legal_moves(board, &color).len())
+                });
+                search.deadline = Some(start + move_time);
             }
-        } else {
-            for _ in target_position..cursor_position {
-                moves.push_back(Move::LEFT)
+            let first = best.as_ref().map(|bot_move| bot_move.action);
+            match search.best_move(board, &color, depth, first) {
+                Some(bot_move) => best = Some(bot_move),
+                None => break,
+            }
+            // A finished game does not change with a deeper search
+            if let Some(BotMove { confidence, .. }) = best {
+                if confidence == 0 || confidence == 100 {
+                    break;
+                }
             }
         }
-        moves.push_back(Move::DROP);
-        return moves;
+        best.unwrap_or(BotMove {
+            action: Action::DROP(0),
+            confidence: 0,
+            score: -WIN_SCORE,
+            depth: 0,
+        })
+    }
+}
+
+impl interface::GameInterface for MinimaxBot {
+    fn name(&self) -> String {
+        String::from("Bot")
+    }
+
+    fn play(&self, game: &Game, cursor_position: u8, color: Token, _: &Window) -> VecDeque<Move> {
+        let action = self
+            .best_move(game.rules(), &mut game.board().clone(), color)
+            .action;
+        interface::moves_to_play(&game.board().config(), cursor_position, action)
     }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::board::Config;
+    use crate::rules::{ClassicRules, MisereRules, PopOutRules, RulesetObject};
+
+    // The minimax search used before alpha-beta pruning, with the same scores, returning the best
+    // score for the bot and counting the positions visited
+    fn plain_minimax(
+        rules: &dyn Ruleset,
+        board: &mut Board,
+        bot_color: &Token,
+        player_color: &Token,
+        depth: u8,
+        ply: u8,
+        nodes: &mut u64,
+    ) -> i32 {
+        let mut scores = Vec::new();
+        for action in rules.legal_moves(board, player_color) {
+            *nodes += 1;
+            rules
+                .apply_move(board, action, player_color)
+                .expect("Error adding token");
+            let score = match rules.outcome(board) {
+                GameOutcome::InProgress => {
+                    let next_player = rules.next_player(board, player_color);
+                    if depth == 0 {
+                        Weights::default().evaluate(rules, board, bot_color, &next_player)
+                    } else {
+                        let ply = ply + 1;
+                        plain_minimax(rules, board, bot_color, &next_player, depth - 1, ply, nodes)
+                    }
+                }
+                GameOutcome::Draw => 0,
+                outcome if outcome.is_winner(bot_color) => win_score(ply + 1),
+                _ => -win_score(ply + 1),
+            };
+            rules.undo_move(board);
+            scores.push(score);
+        }
+        let best = if player_color == bot_color {
+            scores.iter().max()
+        } else {
+            scores.iter().min()
+        };
+        *best.unwrap_or(&0)
+    }
+
+    #[test]
+    fn test_pruning() {
+        let positions: Vec<(&str, Config, RulesetObject)> = vec![
+            ("", Config::default(), Box::new(ClassicRules {})),
+            ("4444", Config::default(), Box::new(ClassicRules {})),
+            ("44433", Config::default(), Box::new(ClassicRules {})),
+            ("4453323", Config::default(), Box::new(ClassicRules {})),
+            ("112233", Config::default(), Box::new(MisereRules {})),
+            ("4455", Config::default(), Box::new(PopOutRules {})),
+            (
+                "345",
+                Config::default().with_players(3).expect("invalid config"),
+                Box::new(ClassicRules {}),
+            ),
+        ];
+        let mut plain_nodes = 0;
+        let mut pruned_nodes = 0;
+        for (moves, config, rules) in positions.into_iter() {
+            let game = Game::from_moves(moves, config, rules).expect("could not parse");
+            let mut board = game.board().clone();
+            let color = game.current_player().clone();
+            let mut nodes = 0;
+            let expected = plain_minimax(
+                game.rules(),
+                &mut board,
+                &color,
+                &color,
+                MIN_DEPTH,
+                0,
+                &mut nodes,
+            );
+
+            let weights = Weights::default();
+            let mut search = Search::new(game.rules(), &weights, color.clone());
+            let bot_move = search
+                .best_move(&mut board, &color, MIN_DEPTH, None)
+                .expect("no move found");
+            assert!(bot_move.score == expected, "{}", moves);
+            assert!(search.nodes < nodes, "{}", moves);
+            plain_nodes += nodes;
+            pruned_nodes += search.nodes;
+        }
+        // Pruning visits a small part of the tree at the same depth
+        assert!(pruned_nodes * 5 < plain_nodes);
+    }
+
+    #[test]
+    fn test_depth_reached() {
+        // The bot gets about the time the search without pruning takes to MIN_DEPTH, and looks
+        // at least three moves further in it
+        for moves in ["", "4453", "44533212"].iter() {
+            let game = Game::from_moves(moves, Config::default(), Box::new(ClassicRules {}))
+                .expect("could not parse");
+            let mut board = game.board().clone();
+            let color = game.current_player().clone();
+            let start = Instant::now();
+            plain_minimax(
+                game.rules(),
+                &mut board,
+                &color,
+                &color,
+                MIN_DEPTH,
+                0,
+                &mut 0,
+            );
+            let plain_time = start.elapsed();
+            let start = Instant::now();
+            let bot_move = MinimaxBot::new().best_move(game.rules(), &mut board, color);
+            assert!(start.elapsed() < plain_time * 3);
+            assert!(
+                bot_move.depth >= MIN_DEPTH + 3,
+                "{} {}",
+                moves,
+                bot_move.depth
+            );
+        }
+
+        // 19607 positions on the classic board, more on larger ones
+        let position_time = Duration::from_micros(1);
+        assert!(move_time(position_time, 7) == Duration::from_micros(19607));
+        assert!(move_time(position_time, 16) > Duration::from_millis(500));
+        assert!(move_time(position_time, 35) == MAX_MOVE_TIME);
+    }
+
+    #[test]
+    fn test_evaluation() {
+        let weights = Weights::default();
+        let classic = ClassicRules {};
+        let board = Board::new();
+        assert!(weights.evaluate(&classic, &board, &Token::YELLOW, &Token::YELLOW) == 0);
+
+        // A token in the center is worth more than one on the edge, and lining up is bad in misère
+        let board = Board::from_moves("41", Config::default()).expect("could not parse");
+        assert!(weights.evaluate(&classic, &board, &Token::YELLOW, &Token::YELLOW) > 0);
+        assert!(weights.evaluate(&classic, &board, &Token::RED, &Token::YELLOW) < 0);
+        assert!(weights.evaluate(&MisereRules {}, &board, &Token::YELLOW, &Token::YELLOW) < 0);
+
+        let threats_only = Weights {
+            open_two: 0,
+            open_three: 0,
+            center: 0,
+            good_threat: 1,
+            other_threat: 0,
+        };
+        // Yellow threatens the bottom row, an odd row. With 36 empty cells, that row is good for
+        // yellow only if yellow moves.
+        let board = Board::from_moves("172736", Config::default()).expect("could not parse");
+        assert!(threats_only.evaluate(&classic, &board, &Token::YELLOW, &Token::YELLOW) == 1);
+        assert!(threats_only.evaluate(&classic, &board, &Token::YELLOW, &Token::RED) == 0);
+    }
+
+    #[test]
+    fn test_ties() {
+        // On an even board the two central columns are as good as each other
+        let config = Config::new(6, 6, 4).expect("invalid config");
+        let weights = Weights::default();
+        let mut played = Vec::new();
+        for _ in 0..40 {
+            let mut board = Board::with_config(config);
+            let bot_move = Search::new(&ClassicRules {}, &weights, Token::YELLOW)
+                .best_move(&mut board, &Token::YELLOW, MIN_DEPTH, None)
+                .expect("no move found");
+            assert!(bot_move.action == Action::DROP(3) || bot_move.action == Action::DROP(4));
+            played.push(bot_move.action);
+        }
+        assert!(played.contains(&Action::DROP(3)) && played.contains(&Action::DROP(4)));
+    }
+
+    #[test]
+    fn test_weights() {
+        let edges = Weights {
+            open_two: 0,
+            open_three: 0,
+            center: -1,
+            good_threat: 0,
+            other_threat: 0,
+        };
+        let mut board = Board::new();
+        let bot_move =
+            MinimaxBot::with_weights(edges).best_move(&ClassicRules {}, &mut board, Token::YELLOW);
+        assert!(bot_move.action == Action::DROP(1) || bot_move.action == Action::DROP(7));
+    }
+
+    #[test]
+    fn test_quickest_win() {
+        // Yellow threatens both the bottom row and the right column, so every move wins, the ones
+        // completing a line right away first
+        let game = Game::from_moves("152635767172", Config::default(), Box::new(ClassicRules {}))
+            .expect("could not parse");
+        let mut board = game.board().clone();
+        let bot_move = Search::new(game.rules(), &Weights::default(), Token::YELLOW)
+            .best_move(&mut board, &Token::YELLOW, MIN_DEPTH, None)
+            .expect("no move found");
+        assert!(bot_move.score == win_score(1));
+        assert!(bot_move.action == Action::DROP(4) || bot_move.action == Action::DROP(7));
+    }
+
+    #[test]
+    fn test_slowest_loss() {
+        // The opponent threatens to win in the column and still wins after the bot blocks it, but
+        // the bot blocks rather than losing right away
+        let positions = [("7636552624", 6, 4), ("236757313324754", 1, 6)];
+        for &(moves, column, length) in positions.iter() {
+            let game = Game::from_moves(moves, Config::default(), Box::new(ClassicRules {}))
+                .expect("could not parse");
+            let color = game.current_player().clone();
+            // The search stops once it finds the loss, whatever the time left
+            let bot = MinimaxBot::new().with_move_time(Duration::from_secs(60));
+            let bot_move = bot.best_move(game.rules(), &mut game.board().clone(), color);
+            assert!(bot_move.confidence == 0, "{}", moves);
+            assert!(bot_move.score == -win_score(length), "{}", moves);
+            assert!(bot_move.action == Action::DROP(column), "{}", moves);
+        }
+    }
 
     #[test]
     fn test_max() {
@@ -199,9 +751,9 @@ mod test {
             .add_token(4, &Token::YELLOW)
             .expect("Could not add token");
 
-        let bot_move = MinimaxBot::max(board.to_number(), Token::YELLOW, Token::RED, 0);
+        let bot_move = MinimaxBot::new().best_move(&ClassicRules {}, &mut board, Token::YELLOW);
         assert!(bot_move.confidence == 100);
-        assert!(bot_move.position == 3);
+        assert!(bot_move.action == Action::DROP(3));
     }
 
     #[test]
@@ -217,9 +769,11 @@ mod test {
             .add_token(4, &Token::YELLOW)
             .expect("Could not add token");
 
-        let bot_move = MinimaxBot::min(board.to_number(), Token::YELLOW, Token::RED, 0);
+        let bot_move = Search::new(&ClassicRules {}, &Weights::default(), Token::RED)
+            .best_move(&mut board, &Token::YELLOW, MIN_DEPTH, None)
+            .expect("no move found");
         assert!(bot_move.confidence == 0);
-        assert!(bot_move.position == 3);
+        assert!(bot_move.action == Action::DROP(3));
     }
 
     #[test]
@@ -240,7 +794,114 @@ mod test {
         board
             .add_token(7, &Token::RED)
             .expect("Could not add token");
-        let bot_move = MinimaxBot::max(board.to_number(), Token::RED, Token::YELLOW, 0);
-        assert!(bot_move.position == 4);
+        let bot_move = MinimaxBot::new().best_move(&ClassicRules {}, &mut board, Token::RED);
+        assert!(bot_move.action == Action::DROP(4));
+    }
+
+    #[test]
+    fn test_connect_three() {
+        let mut board = Board::with_config(Config::new(5, 4, 3).expect("invalid config"));
+        board
+            .add_token(2, &Token::YELLOW)
+            .expect("Could not add token");
+        board
+            .add_token(3, &Token::YELLOW)
+            .expect("Could not add token");
+
+        let bot_move = MinimaxBot::new().best_move(&ClassicRules {}, &mut board, Token::YELLOW);
+        assert!(bot_move.confidence == 100);
+        assert!(bot_move.action == Action::DROP(1) || bot_move.action == Action::DROP(4));
+    }
+
+    #[test]
+    fn test_wrapped_line() {
+        let config = Config::default().with_wrap(true).expect("invalid config");
+        let mut board = Board::with_config(config);
+        for &column in [6, 7, 1].iter() {
+            board
+                .add_token(column, &Token::YELLOW)
+                .expect("Could not add token");
+        }
+        board
+            .add_token(5, &Token::RED)
+            .expect("Could not add token");
+
+        let bot_move = MinimaxBot::new().best_move(&ClassicRules {}, &mut board, Token::YELLOW);
+        assert!(bot_move.confidence == 100);
+        assert!(bot_move.action == Action::DROP(2));
+    }
+
+    #[test]
+    fn test_misere() {
+        let mut board = Board::new();
+        for &column in [1, 2, 3].iter() {
+            board
+                .add_token(column, &Token::YELLOW)
+                .expect("Could not add token");
+        }
+
+        // Completing the line loses, so the bot plays anywhere else
+        let bot_move = MinimaxBot::new().best_move(&MisereRules {}, &mut board, Token::YELLOW);
+        assert!(bot_move.confidence > 0);
+        assert!(bot_move.action != Action::DROP(4));
+    }
+
+    #[test]
+    fn test_paranoid() {
+        let config = Config::new(9, 7, 4)
+            .expect("invalid config")
+            .with_players(3)
+            .expect("invalid config");
+        // Green, playing after red, threatens the end of the bottom row. Yellow must block it,
+        // since red is assumed to help green rather than block.
+        let mut board = Board::with_config(config);
+        board
+            .add_token(3, &Token::YELLOW)
+            .expect("Could not add token");
+        board
+            .add_token(1, &Token::RED)
+            .expect("Could not add token");
+        for &column in [4, 5, 6].iter() {
+            board
+                .add_token(column, &Token::GREEN)
+                .expect("Could not add token");
+        }
+
+        let bot_move = MinimaxBot::new().best_move(&ClassicRules {}, &mut board, Token::YELLOW);
+        assert!(bot_move.action == Action::DROP(7));
+    }
+
+    #[test]
+    fn test_score_four() {
+        let mut board = Board::with_config(Config::score_four());
+        for &column in [6, 10, 14].iter() {
+            board
+                .add_token(column, &Token::YELLOW)
+                .expect("Could not add token");
+        }
+
+        // The line runs from the back to the front row, ending in the second column from the left
+        let bot_move = MinimaxBot::new().best_move(&ClassicRules {}, &mut board, Token::YELLOW);
+        assert!(bot_move.confidence == 100);
+        assert!(bot_move.action == Action::DROP(2));
+    }
+
+    #[test]
+    fn test_blockers() {
+        let config = Config::default()
+            .with_blockers(true)
+            .expect("invalid config");
+        let mut board = Board::with_config(config);
+        board.add_blocker(1, 1).expect("could not add blocker");
+        for &column in [2, 3, 4].iter() {
+            board
+                .add_token(column, &Token::YELLOW)
+                .expect("Could not add token");
+        }
+
+        // The blocker closes the left end of the line, only the right one wins
+        let bot_move = MinimaxBot::new().best_move(&ClassicRules {}, &mut board, Token::YELLOW);
+        assert!(bot_move.confidence == 100);
+        assert!(bot_move.action == Action::DROP(5));
     }
 }
@@ -1,8 +1,9 @@
 use super::interface;
 use crate::board;
+use crate::game::Game;
 use crate::interface::Move;
 use pancurses::Window;
-use rand::Rng;
+use rand::seq::SliceRandom;
 use std::collections::VecDeque;
 
 pub struct RandomBot {}
@@ -16,23 +17,16 @@ impl interface::GameInterface for RandomBot {
 
     fn play(
         &self,
-        _: &board::Board,
+        game: &Game,
         cursor_position: u8,
-        _: board::Token,
+        color: board::Token,
         _: &Window,
     ) -> VecDeque<Move> {
-        let mut moves = VecDeque::new();
-        let target_position = rand::thread_rng().gen_range(1, board::WIDTH + 1);
-        if target_position > cursor_position {
-            for _ in cursor_position..target_position {
-                moves.push_back(Move::RIGHT);
-            }
-        } else {
-            for _ in target_position..cursor_position {
-                moves.push_back(Move::LEFT)
-            }
-        }
-        moves.push_back(Move::DROP);
-        return moves;
+        let legal_moves = game.rules().legal_moves(game.board(), &color);
+        let action = match legal_moves.choose(&mut rand::thread_rng()) {
+            Some(&action) => action,
+            None => return VecDeque::new(),
+        };
+        interface::moves_to_play(&game.board().config(), cursor_position, action)
     }
 }
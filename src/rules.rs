@@ -0,0 +1,532 @@
+use crate::board::{Action, Board, Token};
+use crate::game::GameOutcome;
+
+// The rules of a variant of the game: which moves are legal, what they do to the board and when
+// the game is over. Game, the UI and the bots only go through this trait, so a new variant does
+// not need changes to any of them.
+pub trait Ruleset {
+    fn name(&self) -> String;
+
+    // Moves the player can make
+    fn legal_moves(&self, board: &Board, token: &Token) -> Vec<Action>;
+
+    fn apply_move(
+        &self,
+        board: &mut Board,
+        action: Action,
+        token: &Token,
+    ) -> Result<(), &'static str>;
+
+    // Takes back the last move applied, returning it
+    fn undo_move(&self, board: &mut Board) -> Option<Action> {
+        board.undo_move()
+    }
+
+    // State of the game after the last move played on the board
+    fn outcome(&self, board: &Board) -> GameOutcome;
+
+    // The player moving after the given one has just played
+    fn next_player(&self, board: &Board, token: &Token) -> Token {
+        board.next_player(token)
+    }
+
+    // Names of the special discs the player has left, for variants that have them
+    fn discs(&self, _: &Board, _: &Token) -> Vec<&'static str> {
+        Vec::new()
+    }
+
+    // Whether lining up tokens is what the players are after, not what they must avoid
+    fn lines_win(&self) -> bool {
+        true
+    }
+
+    #[allow(dead_code)]
+    fn is_terminal(&self, board: &Board) -> bool {
+        self.outcome(board) != GameOutcome::InProgress
+    }
+}
+
+pub type RulesetObject = Box<dyn Ruleset>;
+
+// Drop tokens in turn, the first to line up enough of them wins, a full board is a draw
+pub struct ClassicRules {}
+
+impl Ruleset for ClassicRules {
+    fn name(&self) -> String {
+        String::from("Classic")
+    }
+
+    fn legal_moves(&self, board: &Board, _: &Token) -> Vec<Action> {
+        board.legal_moves().into_iter().map(Action::DROP).collect()
+    }
+
+    fn apply_move(
+        &self,
+        board: &mut Board,
+        action: Action,
+        token: &Token,
+    ) -> Result<(), &'static str> {
+        match action {
+            Action::DROP(column) => drop_token(board, column, token),
+            Action::POP(_) => Result::Err("tokens cannot be popped out"),
+            _ => Result::Err("special discs are only played in Power Up"),
+        }
+    }
+
+    fn outcome(&self, board: &Board) -> GameOutcome {
+        let last_move = match board.last_move() {
+            Some((action, _)) => action.column(),
+            None => return GameOutcome::InProgress,
+        };
+        if let Some(win) = board.winner_at_column(last_move) {
+            GameOutcome::from_win(win)
+        } else if board.is_full() {
+            GameOutcome::Draw
+        } else {
+            GameOutcome::InProgress
+        }
+    }
+}
+
+// Completing a line loses the game, a full board is still a draw
+pub struct MisereRules {}
+
+impl Ruleset for MisereRules {
+    fn name(&self) -> String {
+        String::from("Misère")
+    }
+
+    fn legal_moves(&self, board: &Board, token: &Token) -> Vec<Action> {
+        ClassicRules {}.legal_moves(board, token)
+    }
+
+    fn apply_move(
+        &self,
+        board: &mut Board,
+        action: Action,
+        token: &Token,
+    ) -> Result<(), &'static str> {
+        ClassicRules {}.apply_move(board, action, token)
+    }
+
+    fn outcome(&self, board: &Board) -> GameOutcome {
+        match (ClassicRules {}).outcome(board) {
+            GameOutcome::Win { token, line } => GameOutcome::Loss { token, line },
+            outcome => outcome,
+        }
+    }
+
+    fn lines_win(&self) -> bool {
+        false
+    }
+}
+
+fn drop_token(board: &mut Board, column: u8, token: &Token) -> Result<(), &'static str> {
+    match board.add_token(column, token) {
+        Ok(true) => Result::Ok(()),
+        Ok(false) => Result::Err("the column is full"),
+        Err(_) => Result::Err("out of bounds"),
+    }
+}
+
+// Like the classic game, but on their turn players can also pop one of their own tokens out of
+// the bottom of a column. The board filling up does not end the game, as long as the next player
+// can still pop, but the same position coming back a third time with the same player to move is
+// a draw.
+pub struct PopOutRules {}
+
+// Times a position must occur in PopOut for the game to be drawn
+const DRAW_REPETITIONS: usize = 3;
+
+impl Ruleset for PopOutRules {
+    fn name(&self) -> String {
+        String::from("PopOut")
+    }
+
+    fn legal_moves(&self, board: &Board, token: &Token) -> Vec<Action> {
+        let mut moves: Vec<Action> = board.legal_moves().into_iter().map(Action::DROP).collect();
+        moves.extend(
+            (1..=board.columns())
+                .filter(|&column| board.can_pop(column, token))
+                .map(Action::POP),
+        );
+        moves
+    }
+
+    fn apply_move(
+        &self,
+        board: &mut Board,
+        action: Action,
+        token: &Token,
+    ) -> Result<(), &'static str> {
+        match action {
+            Action::DROP(column) => drop_token(board, column, token),
+            Action::POP(column) => match board.pop_token(column, token) {
+                Ok(true) => Result::Ok(()),
+                Ok(false) => Result::Err("the bottom token is not yours"),
+                Err(_) => Result::Err("out of bounds"),
+            },
+            _ => Result::Err("special discs are only played in Power Up"),
+        }
+    }
+
+    fn outcome(&self, board: &Board) -> GameOutcome {
+        match (outcome_anywhere(self, board), board.last_move()) {
+            (GameOutcome::InProgress, Some((_, player)))
+                if board.repetitions(&self.next_player(board, &player)) >= DRAW_REPETITIONS =>
+            {
+                GameOutcome::Draw
+            }
+            (outcome, _) => outcome,
+        }
+    }
+}
+
+// The outcome of a game where moves can change lines away from the column played. A move can
+// complete lines for several players: then the player who moved wins, or else the first of them
+// in turn order. The game is a draw when the next player cannot move.
+fn outcome_anywhere(rules: &dyn Ruleset, board: &Board) -> GameOutcome {
+    let player = match board.last_move() {
+        Some((_, token)) => token,
+        None => return GameOutcome::InProgress,
+    };
+    let mut token = player.clone();
+    for _ in 0..board.players() {
+        if let Some(win) = board.winner(&token) {
+            return GameOutcome::from_win(win);
+        }
+        token = board.next_player(&token);
+    }
+    // Tokens can always be dropped on a board that is not full
+    if board.is_full()
+        && rules
+            .legal_moves(board, &rules.next_player(board, &player))
+            .is_empty()
+    {
+        GameOutcome::Draw
+    } else {
+        GameOutcome::InProgress
+    }
+}
+
+// The special discs of Power Up, each player getting one of each, with their names
+const POWER_DISCS: [(Action, &str); 4] = [
+    (Action::ANVIL(0), "anvil"),
+    (Action::WALL(0), "wall"),
+    (Action::BOMB(0), "bomb"),
+    (Action::EXTRA(0), "extra turn"),
+];
+
+// Like the classic game, but every player also has one of each special disc of Action, to play
+// instead of a token on their turn. Anvils and bombs move tokens around like pops, so lines are
+// looked for on the whole board. Walls are blockers, so players only get one on a board that
+// allows them.
+pub struct PowerUpRules {}
+
+impl PowerUpRules {
+    // Whether the player has the disc of the move and has not played it yet
+    fn has_disc(board: &Board, token: &Token, action: &Action) -> bool {
+        if let Action::WALL(_) = action {
+            if !board.config().blockers {
+                return false;
+            }
+        }
+        !board
+            .moves_of(token)
+            .iter()
+            .any(|played| played.same_kind(action))
+    }
+}
+
+impl Ruleset for PowerUpRules {
+    fn name(&self) -> String {
+        String::from("Power Up")
+    }
+
+    fn legal_moves(&self, board: &Board, token: &Token) -> Vec<Action> {
+        let mut moves = ClassicRules {}.legal_moves(board, token);
+        for (disc, _) in POWER_DISCS.iter() {
+            if PowerUpRules::has_disc(board, token, disc) {
+                moves.extend(
+                    (1..=board.columns())
+                        .map(|column| disc.in_column(column))
+                        .filter(|&action| board.can_play(action, token)),
+                );
+            }
+        }
+        moves
+    }
+
+    fn apply_move(
+        &self,
+        board: &mut Board,
+        action: Action,
+        token: &Token,
+    ) -> Result<(), &'static str> {
+        match action {
+            Action::DROP(column) => drop_token(board, column, token),
+            Action::POP(_) => Result::Err("tokens cannot be popped out"),
+            Action::WALL(_) if !board.config().blockers => {
+                Result::Err("walls need a board with blockers")
+            }
+            _ if !PowerUpRules::has_disc(board, token, &action) => {
+                Result::Err("the disc has already been played")
+            }
+            _ => match board.play(action, token) {
+                Ok(true) => Result::Ok(()),
+                Ok(false) => Result::Err("the disc cannot be played in this column"),
+                Err(_) => Result::Err("out of bounds"),
+            },
+        }
+    }
+
+    fn outcome(&self, board: &Board) -> GameOutcome {
+        outcome_anywhere(self, board)
+    }
+
+    // A player who has just played the extra turn disc moves again
+    fn next_player(&self, board: &Board, token: &Token) -> Token {
+        match board.last_move() {
+            Some((Action::EXTRA(_), player)) if player == *token => player,
+            _ => board.next_player(token),
+        }
+    }
+
+    fn discs(&self, board: &Board, token: &Token) -> Vec<&'static str> {
+        POWER_DISCS
+            .iter()
+            .filter(|(disc, _)| PowerUpRules::has_disc(board, token, disc))
+            .map(|&(_, name)| name)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::board::Config;
+
+    fn drops(columns: &[u8]) -> Vec<Action> {
+        columns.iter().map(|&column| Action::DROP(column)).collect()
+    }
+
+    #[test]
+    fn test_classic_rules() {
+        let rules = ClassicRules {};
+        let mut board = Board::new();
+        assert!(rules.outcome(&board) == GameOutcome::InProgress);
+        assert!(rules.legal_moves(&board, &Token::YELLOW) == drops(&[1, 2, 3, 4, 5, 6, 7]));
+
+        for _ in 0..3 {
+            rules
+                .apply_move(&mut board, Action::DROP(1), &Token::RED)
+                .expect("could not play");
+            assert!(!rules.is_terminal(&board));
+        }
+        rules
+            .apply_move(&mut board, Action::DROP(1), &Token::RED)
+            .expect("could not play");
+        assert!(
+            rules.outcome(&board)
+                == GameOutcome::Win {
+                    token: Token::RED,
+                    line: vec![(1, 1), (1, 2), (1, 3), (1, 4)]
+                }
+        );
+        assert!(rules.undo_move(&mut board) == Some(Action::DROP(1)));
+        assert!(!rules.is_terminal(&board));
+
+        for _ in 0..3 {
+            rules
+                .apply_move(&mut board, Action::DROP(1), &Token::YELLOW)
+                .expect("could not play");
+        }
+        assert!(rules
+            .apply_move(&mut board, Action::DROP(1), &Token::YELLOW)
+            .is_err());
+        assert!(rules
+            .apply_move(&mut board, Action::DROP(0), &Token::YELLOW)
+            .is_err());
+        assert!(rules
+            .apply_move(&mut board, Action::POP(1), &Token::RED)
+            .is_err());
+        assert!(rules.legal_moves(&board, &Token::YELLOW) == drops(&[2, 3, 4, 5, 6, 7]));
+    }
+
+    #[test]
+    fn test_popout_rules() {
+        let rules = PopOutRules {};
+        let mut board = Board::with_config(Config::new(3, 2, 3).expect("invalid config"));
+        rules
+            .apply_move(&mut board, Action::DROP(1), &Token::YELLOW)
+            .expect("could not play");
+        rules
+            .apply_move(&mut board, Action::DROP(2), &Token::RED)
+            .expect("could not play");
+        assert!(
+            rules.legal_moves(&board, &Token::YELLOW)
+                == [
+                    Action::DROP(1),
+                    Action::DROP(2),
+                    Action::DROP(3),
+                    Action::POP(1)
+                ]
+        );
+        assert!(rules
+            .apply_move(&mut board, Action::POP(2), &Token::YELLOW)
+            .is_err());
+        assert!(rules
+            .apply_move(&mut board, Action::POP(3), &Token::YELLOW)
+            .is_err());
+
+        for (column, token) in [(1, Token::YELLOW), (3, Token::RED), (2, Token::YELLOW)].iter() {
+            rules
+                .apply_move(&mut board, Action::DROP(*column), token)
+                .expect("could not play");
+        }
+        rules
+            .apply_move(&mut board, Action::DROP(3), &Token::RED)
+            .expect("could not play");
+        // The board is full but yellow can still pop, so the game goes on
+        assert!(board.is_full());
+        assert!(rules.outcome(&board) == GameOutcome::InProgress);
+        assert!(rules.legal_moves(&board, &Token::YELLOW) == [Action::POP(1)]);
+    }
+
+    #[test]
+    fn test_popout_repetition() {
+        let rules = PopOutRules {};
+        let mut board = Board::new();
+        rules
+            .apply_move(&mut board, Action::DROP(3), &Token::YELLOW)
+            .expect("could not play");
+        let cycle = [
+            (Action::DROP(1), Token::RED),
+            (Action::DROP(2), Token::YELLOW),
+            (Action::POP(1), Token::RED),
+            (Action::POP(2), Token::YELLOW),
+        ];
+        for &(action, ref token) in cycle.iter() {
+            rules
+                .apply_move(&mut board, action, token)
+                .expect("could not play");
+        }
+        assert!(board.repetitions(&Token::RED) == 2);
+        assert!(board.repetitions(&Token::YELLOW) == 1);
+        assert!(rules.outcome(&board) == GameOutcome::InProgress);
+
+        // The third time red is to move with only the yellow token on the board
+        for &(action, ref token) in cycle.iter() {
+            rules
+                .apply_move(&mut board, action, token)
+                .expect("could not play");
+        }
+        assert!(rules.outcome(&board) == GameOutcome::Draw);
+        assert!(board.mirror().repetitions(&Token::RED) == 3);
+        board.undo_move();
+        assert!(rules.outcome(&board) == GameOutcome::InProgress);
+        board.redo_move();
+        assert!(rules.outcome(&board) == GameOutcome::Draw);
+    }
+
+    #[test]
+    fn test_powerup_rules() {
+        let rules = PowerUpRules {};
+        let config = Config::new(3, 2, 3)
+            .and_then(|config| config.with_blockers(true))
+            .expect("invalid config");
+        let mut board = Board::with_config(config);
+        rules
+            .apply_move(&mut board, Action::DROP(1), &Token::YELLOW)
+            .expect("could not play");
+        assert!(
+            rules.legal_moves(&board, &Token::RED)
+                == [
+                    Action::DROP(1),
+                    Action::DROP(2),
+                    Action::DROP(3),
+                    Action::ANVIL(1),
+                    Action::ANVIL(2),
+                    Action::ANVIL(3),
+                    Action::WALL(1),
+                    Action::WALL(2),
+                    Action::WALL(3),
+                    Action::BOMB(1),
+                    Action::EXTRA(1),
+                    Action::EXTRA(2),
+                    Action::EXTRA(3)
+                ]
+        );
+        assert!(rules.discs(&board, &Token::RED) == ["anvil", "wall", "bomb", "extra turn"]);
+
+        // Every disc can only be played once
+        rules
+            .apply_move(&mut board, Action::EXTRA(2), &Token::RED)
+            .expect("could not play");
+        assert!(rules.next_player(&board, &Token::RED) == Token::RED);
+        assert!(rules
+            .apply_move(&mut board, Action::EXTRA(3), &Token::RED)
+            .is_err());
+        assert!(rules.discs(&board, &Token::RED) == ["anvil", "wall", "bomb"]);
+        rules
+            .apply_move(&mut board, Action::BOMB(1), &Token::RED)
+            .expect("could not play");
+        assert!(rules.next_player(&board, &Token::RED) == Token::YELLOW);
+        assert!(rules
+            .apply_move(&mut board, Action::POP(2), &Token::RED)
+            .is_err());
+        assert!(!rules
+            .legal_moves(&board, &Token::RED)
+            .iter()
+            .any(|action| action.same_kind(&Action::BOMB(0))));
+        assert!(ClassicRules {}
+            .apply_move(&mut board, Action::ANVIL(1), &Token::YELLOW)
+            .is_err());
+
+        // The anvil knocks out the red token and completes the bottom row for yellow
+        for &column in [1, 3].iter() {
+            rules
+                .apply_move(&mut board, Action::DROP(column), &Token::YELLOW)
+                .expect("could not play");
+        }
+        assert!(rules.outcome(&board) == GameOutcome::InProgress);
+        rules
+            .apply_move(&mut board, Action::ANVIL(2), &Token::YELLOW)
+            .expect("could not play");
+        assert!(rules.outcome(&board).is_winner(&Token::YELLOW));
+
+        // Without room for blockers there is no wall to play
+        let mut board = Board::new();
+        assert!(rules.discs(&board, &Token::YELLOW) == ["anvil", "bomb", "extra turn"]);
+        assert!(!rules
+            .legal_moves(&board, &Token::YELLOW)
+            .iter()
+            .any(|action| action.same_kind(&Action::WALL(0))));
+        assert!(
+            rules.apply_move(&mut board, Action::WALL(4), &Token::YELLOW)
+                == Err("walls need a board with blockers")
+        );
+    }
+
+    #[test]
+    fn test_misere_rules() {
+        let rules = MisereRules {};
+        let mut board = Board::new();
+        for &column in [1, 2, 3, 4].iter() {
+            rules
+                .apply_move(&mut board, Action::DROP(column), &Token::YELLOW)
+                .expect("could not play");
+        }
+        let outcome = rules.outcome(&board);
+        assert!(
+            outcome
+                == GameOutcome::Loss {
+                    token: Token::YELLOW,
+                    line: vec![(1, 1), (2, 1), (3, 1), (4, 1)]
+                }
+        );
+        assert!(outcome.is_winner(&Token::RED));
+        assert!(!outcome.is_winner(&Token::YELLOW));
+        assert!(rules.is_terminal(&board));
+    }
+}
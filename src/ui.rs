@@ -1,39 +1,50 @@
 extern crate pancurses;
 use super::board;
+use crate::board::Action;
+use crate::game::{Game, GameOutcome};
 use crate::interface::{InterfaceObject, Move};
 use std::collections::VecDeque;
 use std::time;
 
 pub struct UI {
-    board: board::Board,
+    game: Game,
     pub window: pancurses::Window,
-    score_yellow: u8,
-    score_red: u8,
     cursor_position: u8,
-    current_player: board::Token,
-    player1: InterfaceObject,
-    player2: InterfaceObject,
+    // One player for each token of the game, in turn order
+    players: Vec<InterfaceObject>,
 }
 
 impl UI {
     const INPUT_TIMEOUT: i32 = 500;
+    // Spaces between the levels of a three-dimensional board
+    const LAYER_GAP: usize = 2;
 
-    pub fn new(player1: InterfaceObject, player2: InterfaceObject) -> UI {
+    pub fn new(game: Game, players: Vec<InterfaceObject>) -> UI {
         UI {
-            board: board::Board::new(),
+            game,
             window: pancurses::initscr(),
-            score_red: 0,
-            score_yellow: 0,
             cursor_position: 1,
-            current_player: board::Token::YELLOW,
-            player1: player1,
-            player2: player2,
+            players,
         }
     }
 
+    // Color pair of the player's tokens, with black text on top when highlighted
+    fn token_color(token: &board::Token, highlighted: bool) -> pancurses::chtype {
+        pancurses::COLOR_PAIR(match (token, highlighted) {
+            (board::Token::YELLOW, false) => 2,
+            (board::Token::RED, false) => 3,
+            (board::Token::YELLOW, true) => 4,
+            (board::Token::RED, true) => 5,
+            (board::Token::GREEN, false) => 6,
+            (board::Token::BLUE, false) => 7,
+            (board::Token::GREEN, true) => 8,
+            (board::Token::BLUE, true) => 9,
+        })
+    }
+
     fn draw_horizontal_line(&self) {
         self.window.attrset(pancurses::COLOR_PAIR(1));
-        for _ in 1..=board::WIDTH {
+        for _ in 1..=self.game.board().width() {
             self.window.addch('-');
             self.window.addch('-');
             self.window.addch('-');
@@ -43,35 +54,89 @@ impl UI {
     }
 
     fn draw(&self) {
+        let current_board = self.game.board();
+        let highlighted_cells = match self.game.outcome() {
+            GameOutcome::Win { line, .. } | GameOutcome::Loss { line, .. } => line.as_slice(),
+            _ => &[],
+        };
+
         self.window.clear();
-        self.window.printw("Connect Four Game\n\n");
+        self.window.printw(format!(
+            "Connect {} Game ({} rules{})\n\n",
+            current_board.config().connect,
+            self.game.rules().name(),
+            if current_board.config().wrap {
+                ", wrapping around"
+            } else if current_board.config().depth > 1 {
+                ", in 3D"
+            } else {
+                ""
+            }
+        ));
 
         // Draw players scores
-        if self.current_player == board::Token::YELLOW {
-            self.window.addstr("\u{2192}");
+        for (token, player) in self.game.players().iter().zip(self.players.iter()) {
+            if self.game.current_player() == token {
+                self.window.addstr("\u{2192}");
+            } else {
+                self.window.addstr(" ");
+            }
+            self.window.printw(format!(
+                " Player {}: {:02} ",
+                token.index() + 1,
+                self.game.score(token)
+            ));
+            self.window.attrset(UI::token_color(token, false));
+            self.window.addstr("  ");
+            self.window.attrset(pancurses::COLOR_PAIR(1));
+            self.window.addstr(format!(" ({})", player.name()));
+            let discs = self.game.rules().discs(current_board, token);
+            if !discs.is_empty() {
+                self.window.addstr(format!(" discs: {}", discs.join(", ")));
+            }
+            self.window.addch('\n');
+        }
+
+        if current_board.config().depth > 1 {
+            self.draw_layers(highlighted_cells);
         } else {
-            self.window.addstr(" ");
+            self.draw_grid(highlighted_cells);
         }
-        self.window
-            .printw(format!(" Player 1: {:02} ", self.score_yellow));
-        self.window.attrset(pancurses::COLOR_PAIR(2));
-        self.window.addstr("  ");
-        self.window.attrset(pancurses::COLOR_PAIR(1));
-        self.window.addstr(format!(" ({})\n", self.player1.name()));
 
-        if self.current_player == board::Token::RED {
-            self.window.addstr("\u{2192}");
+        // Footer with instructions
+        self.window.addstr(if current_board.config().depth > 1 {
+            "\nPress \u{2190}, \u{2192}, \u{2191} and \u{2193} to move the arrows"
         } else {
-            self.window.addstr(" ");
+            "\nPress \u{2190} and \u{2192} to move the arrow"
+        });
+        self.window.addstr(", SPACE to play a piece, P to pop one out in PopOut, A, W, B or E to play an anvil, wall, bomb or extra turn disc in Power Up, U and R to undo and redo, Q or ESC to quit\n");
+
+        self.window.refresh();
+    }
+
+    // Draws the cell in the given column and row, without its borders
+    fn draw_cell(&self, x: u8, y: u8, highlighted_cells: &[(u8, u8)]) {
+        let current_board = self.game.board();
+        let highlighted = highlighted_cells.contains(&(x, y));
+        if let Some(token) = current_board.get_color_at_cell(x, y) {
+            self.window.attrset(UI::token_color(token, highlighted));
+        }
+
+        if current_board.is_blocker(x, y) {
+            self.window.attrset(pancurses::COLOR_PAIR(10));
+            self.window.addstr("XX");
+        } else if highlighted {
+            self.window.addstr("<>");
+        } else {
+            self.window.addch(' ');
+            self.window.addch(' ');
         }
-        self.window
-            .printw(format!(" Player 2: {:02} ", self.score_red));
-        self.window.attrset(pancurses::COLOR_PAIR(3));
-        self.window.addstr("  ");
         self.window.attrset(pancurses::COLOR_PAIR(1));
-        self.window.addstr(format!(" ({})\n", self.player2.name()));
+    }
 
-        for i in 1..=board::WIDTH {
+    fn draw_grid(&self, highlighted_cells: &[(u8, u8)]) {
+        let current_board = self.game.board();
+        for i in 1..=current_board.width() {
             self.window.addch(' ');
             if i == self.cursor_position {
                 self.window.addstr(" \u{2193}");
@@ -81,139 +146,203 @@ impl UI {
         }
         self.window.addch('\n');
 
-        // Draw grid
-        for h in (1..=board::HEIGHT).rev() {
+        // Draw grid. The sides of a board that wraps around are dotted, as lines go through them.
+        let side = if current_board.config().wrap {
+            ':'
+        } else {
+            '|'
+        };
+        for h in (1..=current_board.height()).rev() {
             self.draw_horizontal_line();
 
-            for x in 1..=board::WIDTH {
+            for x in 1..=current_board.width() {
                 self.window.attrset(pancurses::COLOR_PAIR(1));
-                self.window.addch('|');
-
-                match self.board.get_color_at_cell(x, h) {
-                    Some(board::Token::RED) => {
-                        self.window.attrset(pancurses::COLOR_PAIR(3));
-                    }
-                    Some(board::Token::YELLOW) => {
-                        self.window.attrset(pancurses::COLOR_PAIR(2));
-                    }
-                    None => (),
-                }
-
-                self.window.addch(' ');
-                self.window.addch(' ');
+                self.window.addch(if x == 1 { side } else { '|' });
+                self.draw_cell(x, h, highlighted_cells);
             }
-            self.window.attrset(pancurses::COLOR_PAIR(1));
-            self.window.addch('|');
+            self.window.addch(side);
             self.window.addch('\n');
         }
         self.draw_horizontal_line();
+    }
 
-        // Footer with instructions
-        self.window.addstr("\nPress \u{2190} and \u{2192} to move the arrow, SPACE to play a piece, Q or ESC to quit\n");
+    // A three-dimensional board is drawn one level at a time, from the bottom one on the left.
+    // Each level shows the grid of columns seen from above, the back row at the top, and the
+    // arrows point to the same column on every level.
+    fn draw_layers(&self, highlighted_cells: &[(u8, u8)]) {
+        let config = self.game.board().config();
+        let (cursor_x, cursor_z) = config.column_place(self.cursor_position);
+        let levels = 1..=config.height;
+        let layer_width = 3 * config.width as usize + 1;
+
+        for level in levels.clone() {
+            self.window.addstr(format!(
+                "{:<width$}",
+                format!(" Level {}", level),
+                width = layer_width + UI::LAYER_GAP
+            ));
+        }
+        self.window.addch('\n');
+        for _ in levels.clone() {
+            for x in 1..=config.width {
+                self.window.addch(' ');
+                if x == cursor_x {
+                    self.window.addstr(" \u{2193}");
+                } else {
+                    self.window.addstr("  ");
+                }
+            }
+            self.window.addstr(" ".repeat(1 + UI::LAYER_GAP));
+        }
+        self.window.addch('\n');
 
-        self.window.refresh();
+        let horizontal_line = "-".repeat(layer_width) + &" ".repeat(UI::LAYER_GAP);
+        for z in (1..=config.depth).rev() {
+            self.window
+                .addstr(horizontal_line.repeat(config.height as usize) + "\n");
+            for level in levels.clone() {
+                for x in 1..=config.width {
+                    self.window.addch('|');
+                    self.draw_cell(config.column_at(x, z), level, highlighted_cells);
+                }
+                self.window.addch('|');
+                self.window.addstr(" ".repeat(UI::LAYER_GAP));
+            }
+            if z == cursor_z {
+                self.window.addstr("\u{2190}");
+            }
+            self.window.addch('\n');
+        }
+        self.window
+            .addstr(horizontal_line.repeat(config.height as usize) + "\n");
     }
 
+    // The cursor moves within its row of columns, and from row to row on three-dimensional boards
     fn move_left(&mut self) {
-        if self.cursor_position > 1 {
+        let config = self.game.board().config();
+        if config.column_place(self.cursor_position).0 > 1 {
             self.cursor_position -= 1;
         }
     }
 
     fn move_right(&mut self) {
-        if self.cursor_position < board::WIDTH {
+        let config = self.game.board().config();
+        if config.column_place(self.cursor_position).0 < config.width {
             self.cursor_position += 1;
         }
     }
 
+    fn move_up(&mut self) {
+        let config = self.game.board().config();
+        if config.column_place(self.cursor_position).1 < config.depth {
+            self.cursor_position += config.width;
+        }
+    }
+
+    fn move_down(&mut self) {
+        let config = self.game.board().config();
+        if config.column_place(self.cursor_position).1 > 1 {
+            self.cursor_position -= config.width;
+        }
+    }
+
+    // Subwindow used for the end of game messages, placed over the grid
+    fn overlay_window(&self) -> Result<pancurses::Window, i32> {
+        let config = self.game.board().config();
+        if config.depth > 1 {
+            let layers_width =
+                (3 * config.width as i32 + 1 + UI::LAYER_GAP as i32) * config.height as i32;
+            return self
+                .window
+                .subwin(13, 13, 4 + config.depth as i32, layers_width / 2 - 6);
+        }
+        self.window.subwin(
+            13,
+            13,
+            4 + config.height as i32,
+            3 * config.width as i32 - 6,
+        )
+    }
+
     fn reset(&mut self) {
         std::thread::sleep(time::Duration::from_secs(3));
 
         // Consume all input
         self.window.timeout(0);
-        loop {
-            match self.window.getch() {
-                Some(_) => (),
-                None => break,
-            }
-        }
+        while self.window.getch().is_some() {}
         self.window.timeout(UI::INPUT_TIMEOUT);
-        self.board.reset();
+        self.game.reset();
     }
 
-    fn drop_token(&mut self) {
-        match self
-            .board
-            .add_token(self.cursor_position, &self.current_player)
-        {
-            Ok(true) => {
-                // Check if current player won
-                if self.board.have_winner_at_column(self.cursor_position) {
-                    match self.current_player {
-                        board::Token::YELLOW => self.score_yellow += 1,
-                        board::Token::RED => self.score_red += 1,
-                    }
-
-                    self.draw();
-
-                    match self.window.subwin(13, 13, 10, 15) {
-                        Ok(victory_window) => {
-                            victory_window.addstr("*************");
-                            victory_window.addstr("*           *");
-                            victory_window.addstr("* ");
-
-                            match self.current_player {
-                                board::Token::YELLOW => {
-                                    victory_window.addstr("PLAYER 1");
-                                    victory_window.attrset(pancurses::COLOR_PAIR(2));
-                                    victory_window.addstr(" ");
-                                }
-                                board::Token::RED => {
-                                    victory_window.addstr("PLAYER 2");
-                                    victory_window.attrset(pancurses::COLOR_PAIR(3));
-                                    victory_window.addstr(" ");
-                                }
-                            }
-                            victory_window.attrset(pancurses::COLOR_PAIR(1));
-                            victory_window.addstr(" *");
-
-                            victory_window.addstr("* VICTORY!! *");
-                            victory_window.addstr("*           *");
-                            victory_window.addstr("*************");
-                            victory_window.refresh();
-                        }
-                        Err(_) => (),
-                    }
-
-                    self.reset();
-                }
+    // Shows the end of game message, a line of the overlay width, under the player's name
+    fn show_player_result(&self, token: &board::Token, message: &str) {
+        if let Ok(result_window) = self.overlay_window() {
+            result_window.addstr("*************");
+            result_window.addstr("*           *");
+            result_window.addstr("* ");
+
+            result_window.addstr(format!("PLAYER {}", token.index() + 1));
+            result_window.attrset(UI::token_color(token, false));
+            result_window.addstr(" ");
+            result_window.attrset(pancurses::COLOR_PAIR(1));
+            result_window.addstr(" *");
+
+            result_window.addstr(message);
+            result_window.addstr("*           *");
+            result_window.addstr("*************");
+            result_window.refresh();
+        }
+    }
 
-                // Check if there is a draw
-                if self.board.is_full() {
-                    self.draw();
-
-                    match self.window.subwin(13, 13, 10, 15) {
-                        Ok(draw_window) => {
-                            draw_window.addstr("*************");
-                            draw_window.addstr("*           *");
-                            draw_window.addstr("*   DRAW!   *");
-                            draw_window.addstr("*           *");
-                            draw_window.addstr("*************");
-                            draw_window.refresh();
-                        }
-                        Err(_) => (),
-                    }
-                    self.reset();
-                }
+    fn play_move(&mut self, action: Action) {
+        if let Ok(GameOutcome::InProgress) | Err(_) = self.game.play(action) {
+            return;
+        }
 
-                // Switch current player (note: we do even if a player won)
-                self.current_player = match self.current_player {
-                    board::Token::YELLOW => board::Token::RED,
-                    board::Token::RED => board::Token::YELLOW,
+        self.draw();
+        match self.game.outcome() {
+            GameOutcome::Win { token, .. } => {
+                // Show the winning line for a moment before the victory message
+                std::thread::sleep(time::Duration::from_secs(1));
+                self.show_player_result(token, "* VICTORY!! *");
+            }
+            GameOutcome::Loss { token, .. } => {
+                // In misère games the line is shown with the name of the player who lost
+                std::thread::sleep(time::Duration::from_secs(1));
+                self.show_player_result(token, "*  DEFEAT!  *");
+            }
+            GameOutcome::Draw => {
+                if let Ok(draw_window) = self.overlay_window() {
+                    draw_window.addstr("*************");
+                    draw_window.addstr("*           *");
+                    draw_window.addstr("*   DRAW!   *");
+                    draw_window.addstr("*           *");
+                    draw_window.addstr("*************");
+                    draw_window.refresh();
                 }
             }
-            Ok(false) => (),
-            Err(_) => (),
+            GameOutcome::InProgress => (),
+        }
+        self.reset();
+    }
+
+    // Takes back moves until it is again the turn of the player asking for it, so that playing
+    // against a bot also removes the bot's answer
+    fn undo_move(&mut self) {
+        let player = self.game.current_player().clone();
+        while self.game.undo_move().is_some() {
+            if *self.game.current_player() == player {
+                break;
+            }
+        }
+    }
+
+    fn redo_move(&mut self) {
+        let player = self.game.current_player().clone();
+        while self.game.redo_move().is_some() {
+            if *self.game.current_player() == player {
+                break;
+            }
         }
     }
 
@@ -222,10 +351,40 @@ impl UI {
             match player_move {
                 Move::LEFT => self.move_left(),
                 Move::RIGHT => self.move_right(),
+                Move::UP => self.move_up(),
+                Move::DOWN => self.move_down(),
                 Move::DROP => {
-                    self.drop_token();
+                    self.play_move(Action::DROP(self.cursor_position));
                     break; // Players can only queue a single drop, and must be the last action
                 }
+                Move::POP => {
+                    self.play_move(Action::POP(self.cursor_position));
+                    break;
+                }
+                Move::ANVIL => {
+                    self.play_move(Action::ANVIL(self.cursor_position));
+                    break;
+                }
+                Move::WALL => {
+                    self.play_move(Action::WALL(self.cursor_position));
+                    break;
+                }
+                Move::BOMB => {
+                    self.play_move(Action::BOMB(self.cursor_position));
+                    break;
+                }
+                Move::EXTRA => {
+                    self.play_move(Action::EXTRA(self.cursor_position));
+                    break;
+                }
+                Move::UNDO => {
+                    self.undo_move();
+                    break;
+                }
+                Move::REDO => {
+                    self.redo_move();
+                    break;
+                }
             }
             self.draw();
             std::thread::sleep(time::Duration::from_millis(100));
@@ -241,26 +400,26 @@ impl UI {
         pancurses::init_pair(1, pancurses::COLOR_WHITE, pancurses::COLOR_BLACK);
         pancurses::init_pair(2, pancurses::COLOR_YELLOW, pancurses::COLOR_YELLOW);
         pancurses::init_pair(3, pancurses::COLOR_RED, pancurses::COLOR_RED);
+        pancurses::init_pair(4, pancurses::COLOR_BLACK, pancurses::COLOR_YELLOW);
+        pancurses::init_pair(5, pancurses::COLOR_BLACK, pancurses::COLOR_RED);
+        pancurses::init_pair(6, pancurses::COLOR_GREEN, pancurses::COLOR_GREEN);
+        pancurses::init_pair(7, pancurses::COLOR_BLUE, pancurses::COLOR_BLUE);
+        pancurses::init_pair(8, pancurses::COLOR_BLACK, pancurses::COLOR_GREEN);
+        pancurses::init_pair(9, pancurses::COLOR_BLACK, pancurses::COLOR_BLUE);
+        pancurses::init_pair(10, pancurses::COLOR_BLACK, pancurses::COLOR_WHITE);
 
         self.window.timeout(UI::INPUT_TIMEOUT);
 
         loop {
             UI::draw(self);
 
-            self.process_moves(match self.current_player {
-                board::Token::YELLOW => self.player1.play(
-                    &self.board,
-                    self.cursor_position,
-                    board::Token::YELLOW,
-                    &self.window,
-                ),
-                board::Token::RED => self.player2.play(
-                    &self.board,
-                    self.cursor_position,
-                    board::Token::RED,
-                    &self.window,
-                ),
-            });
+            let current_player = self.game.current_player().clone();
+            self.process_moves(self.players[current_player.index()].play(
+                &self.game,
+                self.cursor_position,
+                current_player,
+                &self.window,
+            ));
 
             // Consume input and check if we have to close
             let mut shall_close = false;